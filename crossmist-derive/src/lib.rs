@@ -145,32 +145,53 @@ pub fn func(meta: TokenStream, input: TokenStream) -> TokenStream {
     let impl_code = if has_references {
         quote! {}
     } else {
-        let spawn = quote! { spawn(#type_ident::entry::#generics, (#(#arg_names,)*)) };
+        let function = quote! { ::core::concat!(::core::module_path!(), "::", ::core::stringify!(#ident)) };
+        let spawn = quote! { spawn(#type_ident::entry::#generics, #function, crossmist_options, (#(#arg_names,)*)) };
+        let options_arg = quote! { crossmist_options: &::crossmist::SpawnOptions };
+        let default_options = quote! { &::crossmist::SpawnOptions::new() };
 
         quote! {
             pub fn spawn #generic_params(&self, #fn_args) -> ::std::io::Result<::crossmist::Child<#return_type>> {
+                self.spawn_with(#default_options, #(#arg_names,)*)
+            }
+            pub fn spawn_with #generic_params(&self, #options_arg, #fn_args) -> ::std::io::Result<::crossmist::Child<#return_type>> {
                 unsafe { ::crossmist::blocking::#spawn }
             }
             pub fn run #generic_params(&self, #fn_args) -> ::std::io::Result<#return_type> {
                 self.spawn(#(#arg_names,)*)?.join()
             }
+            pub fn run_with #generic_params(&self, #options_arg, #fn_args) -> ::std::io::Result<#return_type> {
+                self.spawn_with(crossmist_options, #(#arg_names,)*)?.join()
+            }
 
             ::crossmist::if_tokio! {
                 pub async fn spawn_tokio #generic_params(&self, #fn_args) -> ::std::io::Result<::crossmist::tokio::Child<#return_type>> {
+                    self.spawn_tokio_with(#default_options, #(#arg_names,)*).await
+                }
+                pub async fn spawn_tokio_with #generic_params(&self, #options_arg, #fn_args) -> ::std::io::Result<::crossmist::tokio::Child<#return_type>> {
                     unsafe { ::crossmist::tokio::#spawn.await }
                 }
                 pub async fn run_tokio #generic_params(&self, #fn_args) -> ::std::io::Result<#return_type> {
                     self.spawn_tokio(#(#arg_names,)*).await?.join().await
                 }
+                pub async fn run_tokio_with #generic_params(&self, #options_arg, #fn_args) -> ::std::io::Result<#return_type> {
+                    self.spawn_tokio_with(crossmist_options, #(#arg_names,)*).await?.join().await
+                }
             }
 
             ::crossmist::if_smol! {
                 pub async fn spawn_smol #generic_params(&self, #fn_args) -> ::std::io::Result<::crossmist::smol::Child<#return_type>> {
+                    self.spawn_smol_with(#default_options, #(#arg_names,)*).await
+                }
+                pub async fn spawn_smol_with #generic_params(&self, #options_arg, #fn_args) -> ::std::io::Result<::crossmist::smol::Child<#return_type>> {
                     unsafe { ::crossmist::smol::#spawn.await }
                 }
                 pub async fn run_smol #generic_params(&self, #fn_args) -> ::std::io::Result<#return_type> {
                     self.spawn_smol(#(#arg_names,)*).await?.join().await
                 }
+                pub async fn run_smol_with #generic_params(&self, #options_arg, #fn_args) -> ::std::io::Result<#return_type> {
+                    self.spawn_smol_with(crossmist_options, #(#arg_names,)*).await?.join().await
+                }
             }
         }
     };
@@ -216,6 +237,425 @@ pub fn func(meta: TokenStream, input: TokenStream) -> TokenStream {
     TokenStream::from(expanded)
 }
 
+#[proc_macro_attribute]
+pub fn main(meta: TokenStream, input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as syn::ItemFn);
+    let init = quote! { ::crossmist::init(); };
+    wrap_entrypoint("main", meta, input, init, quote! {})
+}
+
+#[proc_macro_attribute]
+pub fn test(meta: TokenStream, input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as syn::ItemFn);
+    let ident = &input.sig.ident;
+    let init = quote! { ::crossmist::imp::init_test(::core::module_path!(), ::core::stringify!(#ident)); };
+    wrap_entrypoint("test", meta, input, init, quote! { #[test] })
+}
+
+// Implements `#[crossmist::main]` and `#[crossmist::test]`.
+fn wrap_entrypoint(
+    name: &str,
+    meta: TokenStream,
+    mut input: syn::ItemFn,
+    init: impl ToTokens,
+    outer_attribute: impl ToTokens,
+) -> TokenStream {
+    let mut runtime = None;
+
+    let args = parse_macro_input!(meta with Punctuated::<Meta, syn::Token![,]>::parse_terminated);
+    for arg in args {
+        if runtime.is_some() {
+            return quote_spanned! { arg.span() => compile_error!("Only one runtime can be specified"); }
+                .into();
+        }
+        if arg.path().is_ident("tokio") {
+            // `#[tokio::test]` can't be used here, since it marks the inner function as a test.
+            // Match its defaults instead.
+            runtime = Some(match arg {
+                Meta::Path(_) if name == "test" => {
+                    quote! { #[tokio::main(flavor = "current_thread")] }
+                }
+                Meta::Path(_) => quote! { #[tokio::main] },
+                Meta::List(MetaList { nested, .. }) => quote! { #[tokio::main(#nested)] },
+                Meta::NameValue(..) => {
+                    return quote_spanned! { arg.span() => compile_error!("Invalid syntax for 'tokio' argument"); }.into();
+                }
+            });
+        } else if arg.path().is_ident("smol") {
+            if !matches!(arg, Meta::Path(_)) {
+                return quote_spanned! { arg.span() => compile_error!("Invalid syntax for 'smol' argument"); }.into();
+            }
+            runtime = Some(quote! {});
+        } else {
+            return quote_spanned! { arg.span() => compile_error!("Unknown attribute argument"); }
+                .into();
+        }
+    }
+
+    for attr in &input.attrs {
+        let segments: Vec<String> = attr
+            .path
+            .segments
+            .iter()
+            .map(|segment| segment.ident.to_string())
+            .collect();
+        if segments.len() == 2 && segments[1] == name && segments[0] != "crossmist" {
+            let message = format!("#[crossmist::{name}] starts the runtime on its own, use #[crossmist::{name}(tokio)] or #[crossmist::{name}(smol)] instead");
+            return quote_spanned! { attr.span() => compile_error!(#message); }.into();
+        }
+        if segments == ["crossmist", name] {
+            let message = format!("#[crossmist::{name}] is applied twice");
+            return quote_spanned! { attr.span() => compile_error!(#message); }.into();
+        }
+    }
+
+    let is_async = input.sig.asyncness.is_some();
+    if is_async != runtime.is_some() {
+        let message = if is_async {
+            format!("Async {name} requires a runtime, use #[crossmist::{name}(tokio)] or #[crossmist::{name}(smol)]")
+        } else {
+            format!("A runtime can only be used with async {name}")
+        };
+        return quote_spanned! { input.sig.span() => compile_error!(#message); }.into();
+    }
+
+    let attrs = core::mem::take(&mut input.attrs);
+    let vis = core::mem::replace(&mut input.vis, syn::Visibility::Inherited);
+    let ident = core::mem::replace(&mut input.sig.ident, format_ident!("crossmist_{}", name));
+    let inner_ident = &input.sig.ident;
+    let output = &input.sig.output;
+
+    // The runtime must be started only after crossmist is initialized, since child processes never
+    // return from initialization.
+    let body = match runtime {
+        Some(ref attribute) if !attribute.is_empty() => quote! {
+            #attribute
+            #input
+            #inner_ident()
+        },
+        Some(_) => quote! {
+            #input
+            ::crossmist::imp::async_io::block_on(#inner_ident())
+        },
+        None => quote! {
+            #input
+            #inner_ident()
+        },
+    };
+
+    let expanded = quote! {
+        #outer_attribute
+        #(#attrs)*
+        #vis fn #ident() #output {
+            #init
+            #body
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
+#[proc_macro_attribute]
+pub fn service(meta: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(meta with Punctuated::<Meta, syn::Token![,]>::parse_terminated);
+    if let Some(arg) = args.first() {
+        return quote_spanned! { arg.span() => compile_error!("Unknown attribute argument"); }
+            .into();
+    }
+
+    let input = parse_macro_input!(input as syn::ItemTrait);
+    if !input.generics.params.is_empty() {
+        return quote_spanned! { input.generics.span() => compile_error!("Generic services are not supported"); }
+            .into();
+    }
+
+    let vis = &input.vis;
+    let ident = &input.ident;
+    let call_ident = format_ident!("{}Call", ident);
+    let response_ident = format_ident!("{}Response", ident);
+    let client_ident = format_ident!("{}Client", ident);
+    let server_ident = format_ident!("{}Server", ident);
+
+    let mut variants = Vec::new();
+    let mut response_variants = Vec::new();
+    let mut dispatch_arms = Vec::new();
+    let mut sync_methods = Vec::new();
+    let mut async_methods = Vec::new();
+    let mut has_async = false;
+
+    for item in &input.items {
+        let syn::TraitItem::Method(method) = item else {
+            continue;
+        };
+        let sig = &method.sig;
+        if !sig.generics.params.is_empty() {
+            return quote_spanned! { sig.generics.span() => compile_error!("Generic service methods are not supported"); }
+                .into();
+        }
+        if let Some(unsafety) = sig.unsafety {
+            return quote_spanned! { unsafety.span() => compile_error!("Unsafe service methods are not supported"); }
+                .into();
+        }
+        if ["new", "into_inner", "channel"].contains(&sig.ident.to_string().as_str()) {
+            let message = format!(
+                "Service methods cannot be named `{}`, which is used by the generated client",
+                sig.ident,
+            );
+            return quote_spanned! { sig.ident.span() => compile_error!(#message); }.into();
+        }
+        match sig.inputs.first() {
+            Some(syn::FnArg::Receiver(receiver)) if receiver.reference.is_some() => {}
+            _ => {
+                return quote_spanned! { sig.span() => compile_error!("Service methods must take `&self` or `&mut self`"); }
+                    .into();
+            }
+        }
+
+        let mut arg_names = Vec::new();
+        let mut arg_types = Vec::new();
+        for arg in sig.inputs.iter().skip(1) {
+            let syn::FnArg::Typed(pattype) = arg else {
+                unreachable!();
+            };
+            let syn::Pat::Ident(ref patident) = *pattype.pat else {
+                return quote_spanned! { pattype.pat.span() => compile_error!("Service method arguments must be identifiers"); }
+                    .into();
+            };
+            arg_names.push(&patident.ident);
+            arg_types.push(&pattype.ty);
+        }
+
+        let method_ident = &sig.ident;
+        let variant = format_ident!("{}", to_camel_case(&method_ident.to_string()));
+        let output = match sig.output {
+            syn::ReturnType::Default => quote! { () },
+            syn::ReturnType::Type(_, ref ty) => quote! { #ty },
+        };
+        let docs: Vec<_> = method
+            .attrs
+            .iter()
+            .filter(|attr| attr.path.is_ident("doc"))
+            .collect();
+        let await_ = if sig.asyncness.is_some() {
+            has_async = true;
+            quote! { .await }
+        } else {
+            quote! {}
+        };
+
+        variants.push(quote! {
+            #variant { #(#arg_names: #arg_types,)* }
+        });
+        response_variants.push(quote! {
+            #variant(#output)
+        });
+        // The arguments are bound to generated names, which can't clash with `service`.
+        let bindings: Vec<_> = (0..arg_names.len())
+            .map(|i| format_ident!("__arg{}", i))
+            .collect();
+        dispatch_arms.push(quote! {
+            Self::#variant { #(#arg_names: #bindings,)* } => #response_ident::#variant(service.#method_ident(#(#bindings,)*)#await_)
+        });
+
+        let call = quote! { #call_ident::#variant { #(#arg_names,)* } };
+        let unwrap = quote! {
+            #response_ident::#variant(value) => ::std::result::Result::Ok(value),
+            #[allow(unreachable_patterns)]
+            _ => ::std::result::Result::Err(::std::io::Error::new(
+                ::std::io::ErrorKind::InvalidData,
+                "Unexpected response to a service call",
+            )),
+        };
+        sync_methods.push(quote! {
+            #(#docs)*
+            pub fn #method_ident(&mut self, #(#arg_names: #arg_types,)*) -> ::std::io::Result<#output> {
+                match self.0.request(#call)? {
+                    #unwrap
+                }
+            }
+        });
+        async_methods.push(quote! {
+            #(#docs)*
+            pub async fn #method_ident(&mut self, #(#arg_names: #arg_types,)*) -> ::std::io::Result<#output> {
+                match self.0.request(#call).await? {
+                    #unwrap
+                }
+            }
+        });
+    }
+
+    let dispatch_async = if has_async {
+        quote! { async }
+    } else {
+        quote! {}
+    };
+    let dispatch_await = if has_async {
+        quote! { .await }
+    } else {
+        quote! {}
+    };
+
+    // Async methods can't be run by a synchronous server.
+    let sync_serve = if has_async {
+        quote! {}
+    } else {
+        quote! {
+            /// Handle calls until the client is dropped.
+            pub fn serve(mut self, service: &mut (impl #ident + ?Sized)) -> ::std::io::Result<()> {
+                while let Some(call) = self.0.recv()? {
+                    self.0.send(call.dispatch(service))?;
+                }
+                Ok(())
+            }
+        }
+    };
+
+    let async_impls = |runtime: syn::Ident| {
+        quote! {
+            impl #client_ident<::crossmist::#runtime::Duplex<#call_ident, #response_ident>> {
+                #(#async_methods)*
+            }
+
+            impl #server_ident<::crossmist::#runtime::Duplex<#response_ident, #call_ident>> {
+                /// Handle calls until the client is dropped.
+                pub async fn serve(mut self, service: &mut (impl #ident + ?Sized)) -> ::std::io::Result<()> {
+                    while let Some(call) = self.0.recv().await? {
+                        self.0.send(call.dispatch(service)#dispatch_await).await?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+    };
+    let tokio_impls = async_impls(format_ident!("tokio"));
+    let smol_impls = async_impls(format_ident!("smol"));
+
+    let call_doc = format!("A call to a method of [`{ident}`].");
+    let response_doc = format!("The result of a method of [`{ident}`].");
+    let client_doc = format!(
+        "A client of a [`{ident}`] service, which usually runs in another process.\n\n\
+         `Chan` is [`crossmist::Duplex`](::crossmist::Duplex), \
+         [`crossmist::tokio::Duplex`](::crossmist::tokio::Duplex), or \
+         [`crossmist::smol::Duplex`](::crossmist::smol::Duplex)."
+    );
+    let server_doc = format!(
+        "The serving side of a [`{ident}`] service.\n\n`Chan` is the same kind of channel as in \
+         [`{client_ident}`]."
+    );
+
+    let expanded = quote! {
+        #input
+
+        #[doc = #call_doc]
+        #[derive(::crossmist::Object)]
+        #vis enum #call_ident {
+            #(#variants,)*
+        }
+
+        #[doc = #response_doc]
+        #[derive(::crossmist::Object)]
+        #vis enum #response_ident {
+            #(#response_variants,)*
+        }
+
+        impl #call_ident {
+            /// Call the method on `service`.
+            pub #dispatch_async fn dispatch(self, service: &mut (impl #ident + ?Sized)) -> #response_ident {
+                match self {
+                    #(#dispatch_arms,)*
+                }
+            }
+        }
+
+        #[doc = #client_doc]
+        #[derive(Debug)]
+        #vis struct #client_ident<Chan = ::crossmist::Duplex<#call_ident, #response_ident>>(Chan);
+
+        unsafe impl<Chan: ::crossmist::Object> ::crossmist::Object for #client_ident<Chan> {
+            fn serialize_self(self, s: &mut ::crossmist::Serializer) {
+                s.serialize(self.0);
+            }
+            unsafe fn deserialize_self(d: &mut ::crossmist::Deserializer) -> Self {
+                Self(unsafe { d.deserialize() })
+            }
+        }
+
+        impl<Chan> #client_ident<Chan> {
+            /// Wrap a channel to the server.
+            pub fn new(chan: Chan) -> Self {
+                Self(chan)
+            }
+
+            /// Return the underlying channel.
+            pub fn into_inner(self) -> Chan {
+                self.0
+            }
+        }
+
+        impl #client_ident {
+            /// Create a connected client and server.
+            pub fn channel() -> ::std::io::Result<(Self, #server_ident)> {
+                let (client, server) = ::crossmist::duplex()?;
+                Ok((Self(client), #server_ident(server)))
+            }
+
+            #(#sync_methods)*
+        }
+
+        #[doc = #server_doc]
+        #[derive(Debug)]
+        #vis struct #server_ident<Chan = ::crossmist::Duplex<#response_ident, #call_ident>>(Chan);
+
+        unsafe impl<Chan: ::crossmist::Object> ::crossmist::Object for #server_ident<Chan> {
+            fn serialize_self(self, s: &mut ::crossmist::Serializer) {
+                s.serialize(self.0);
+            }
+            unsafe fn deserialize_self(d: &mut ::crossmist::Deserializer) -> Self {
+                Self(unsafe { d.deserialize() })
+            }
+        }
+
+        impl<Chan> #server_ident<Chan> {
+            /// Wrap a channel to the client.
+            pub fn new(chan: Chan) -> Self {
+                Self(chan)
+            }
+
+            /// Return the underlying channel.
+            pub fn into_inner(self) -> Chan {
+                self.0
+            }
+        }
+
+        impl #server_ident {
+            #sync_serve
+        }
+
+        ::crossmist::if_tokio! {
+            #tokio_impls
+        }
+
+        ::crossmist::if_smol! {
+            #smol_impls
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
+// Convert a method name to a variant name, e.g. `get_value` to `GetValue`.
+fn to_camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
 #[proc_macro_derive(Object)]
 pub fn derive_object(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
@@ -307,17 +747,20 @@ pub fn derive_object(input: TokenStream) -> TokenStream {
                 let ident = &variant.ident;
                 match &variant.fields {
                     syn::Fields::Named(fields) => {
+                        // Bind the fields to generated names, which can't clash with `s`.
                         let (names, sers): (Vec<_>, Vec<_>) = fields
                             .named
                             .iter()
-                            .map(|field| {
+                            .enumerate()
+                            .map(|(i, field)| {
                                 let ident = &field.ident;
-                                (quote! { #ident }, quote! { s.serialize(#ident); })
+                                let binding = format_ident!("a{}", i);
+                                (quote! { #ident: #binding }, quote! { s.serialize(#binding); })
                             })
                             .unzip();
                         quote! {
-                            Self::#ident{ #(#names,)* } => {
-                                s.serialize(#i as usize);
+                            Self::#ident { #(#names,)* } => {
+                                s.serialize(#i);
                                 #(#sers)*
                             }
                         }
@@ -326,12 +769,12 @@ pub fn derive_object(input: TokenStream) -> TokenStream {
                         let (refs, sers): (Vec<_>, Vec<_>) = (0..fields.unnamed.len())
                             .map(|i| {
                                 let ident = format_ident!("a{}", i);
-                                (quote! { ref #ident }, quote! { s.serialize(#ident); })
+                                (quote! { #ident }, quote! { s.serialize(#ident); })
                             })
                             .unzip();
                         quote! {
                             Self::#ident(#(#refs,)*) => {
-                                s.serialize(&(#i as usize));
+                                s.serialize(#i);
                                 #(#sers)*
                             }
                         }
@@ -339,7 +782,7 @@ pub fn derive_object(input: TokenStream) -> TokenStream {
                     syn::Fields::Unit => {
                         quote! {
                             Self::#ident => {
-                                s.serialize(&(#i as usize));
+                                s.serialize(#i);
                             }
                         }
                     }
@@ -359,16 +802,16 @@ pub fn derive_object(input: TokenStream) -> TokenStream {
                                 quote! { #ident: unsafe { d.deserialize() } }
                             })
                             .collect();
-                        quote! { #i => Ok(Self::#ident{ #(#des,)* }) }
+                        quote! { #i => Self::#ident { #(#des,)* } }
                     }
                     syn::Fields::Unnamed(fields) => {
                         let des: Vec<_> = (0..fields.unnamed.len())
                             .map(|_| quote! { unsafe { d.deserialize() } })
                             .collect();
-                        quote! { #i => Ok(Self::#ident(#(#des,)*)) }
+                        quote! { #i => Self::#ident(#(#des,)*) }
                     }
                     syn::Fields::Unit => {
-                        quote! { #i => Ok(Self::#ident) }
+                        quote! { #i => Self::#ident }
                     }
                 }
             });
@@ -380,8 +823,8 @@ pub fn derive_object(input: TokenStream) -> TokenStream {
                             #(#serialize_variants,)*
                         }
                     }
-                    unsafe fn deserialize_self(d: &mut ::crossmist::Deserializer) -> ::std::io::Result<Self> {
-                        match d.deserialize::<usize>() {
+                    unsafe fn deserialize_self(d: &mut ::crossmist::Deserializer) -> Self {
+                        match unsafe { d.deserialize::<usize>() } {
                             #(#deserialize_variants,)*
                             _ => panic!("Unexpected enum variant"),
                         }
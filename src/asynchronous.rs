@@ -13,6 +13,25 @@
 //! child. In this case, you would create a channel using [`crossmist::channel`] and convert one
 //! side to an asynchronous one.
 //!
+//! With the `futures` feature enabled, [`Receiver`] implements [`Stream`](futures_core::Stream)
+//! and [`Sender`] implements [`Sink`](futures_sink::Sink), so channels can be used with the
+//! combinators from the `futures` crate:
+//!
+//! ```ignore
+//! use futures::{StreamExt, TryStreamExt};
+//!
+//! // Double each number received from `rx` and send it to `tx`.
+//! rx.map_ok(|x| x * 2).forward(tx).await?;
+//! ```
+//!
+//! Closing the sink only flushes it: the other side sees the channel closed once the sender is
+//! dropped.
+//!
+//! Receiving and sending is cancel-safe, so `recv` and `send` can be used in `select!` and with
+//! timeouts: a message that is partially transferred when the future is dropped is kept in the
+//! channel and finished by the next call. Until then, the channel cannot be passed to another
+//! process.
+//!
 //!
 //! ## Processes
 //!
@@ -41,12 +60,18 @@
 //! let child = my_process.spawn_tokio().await?;
 //! ```
 
-use crate::{Deserializer, Object, Serializer, StaticFn, imp, subprocess};
+use crate::{
+    Deserializer, Object, Serializer, SpawnOptions, StaticFn, imp, options::SetupError, setup,
+    subprocess,
+};
 use std::fmt;
-use std::future::Future;
+use std::future::{Future, poll_fn};
 use std::io::{Error, ErrorKind, Result};
 use std::marker::PhantomData;
+#[cfg(feature = "futures")]
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
 #[cfg(unix)]
 use {
     crate::internals::{SingleObjectReceiver, SingleObjectSender, socketpair},
@@ -95,6 +120,26 @@ pub unsafe trait AsyncStream: Object + AsFd + AsRawFd + Sized {
         &self,
         f: impl FnMut() -> Result<T> + Send,
     ) -> impl Future<Output = Result<T>> + Send;
+
+    /// Poll a blocking write.
+    ///
+    /// Like [`blocking_write`](Self::blocking_write), but instead of waiting until the stream is
+    /// writable, registers the waker from `cx` and returns `Poll::Pending`.
+    fn poll_blocking_write<T>(
+        &self,
+        cx: &mut Context<'_>,
+        f: impl FnMut() -> Result<T>,
+    ) -> Poll<Result<T>>;
+
+    /// Poll a blocking read.
+    ///
+    /// Like [`blocking_read`](Self::blocking_read), but instead of waiting until the stream is
+    /// readable, registers the waker from `cx` and returns `Poll::Pending`.
+    fn poll_blocking_read<T>(
+        &self,
+        cx: &mut Context<'_>,
+        f: impl FnMut() -> Result<T>,
+    ) -> Poll<Result<T>>;
 }
 
 /// Runtime-dependent stream implementation.
@@ -110,23 +155,31 @@ pub unsafe trait AsyncStream: Object + AsSocket + AsRawSocket + Sized {
     /// Perform a read.
     #[cfg(windows)]
     fn read(&mut self, buf: &mut [u8]) -> impl Future<Output = Result<()>> + Send;
+
+    /// Attempt a partial write, returning the number of bytes written.
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>>;
+
+    /// Attempt a partial read, returning the number of bytes read.
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>>;
 }
 
 /// The transmitting side of a unidirectional channel.
 ///
 /// `T` is the type of the objects this side sends via the channel and the other side receives.
-#[derive(Object)]
 pub struct Sender<Stream: AsyncStream, T: Object> {
     pub(crate) fd: Stream,
+    // A message that has been accepted but not sent in full yet.
+    sending: Option<Box<Sending>>,
     marker: PhantomData<fn(T)>,
 }
 
 /// The receiving side of a unidirectional channel.
 ///
 /// `T` is the type of the objects the other side sends via the channel and this side receives.
-#[derive(Object)]
 pub struct Receiver<Stream: AsyncStream, T: Object> {
     pub(crate) fd: Stream,
+    // A message that has been partially received.
+    receiving: Option<Box<Receiving<T>>>,
     marker: PhantomData<fn() -> T>,
 }
 
@@ -134,12 +187,217 @@ pub struct Receiver<Stream: AsyncStream, T: Object> {
 ///
 /// `S` is the type of the objects this side sends via the channel and the other side receives, `R`
 /// is the type of the objects the other side sends via the channel and this side receives.
-#[derive(Object)]
 pub struct Duplex<Stream: AsyncStream, S: Object, R: Object> {
     pub(crate) fd: Stream,
+    sending: Option<Box<Sending>>,
+    receiving: Option<Box<Receiving<R>>>,
     marker: PhantomData<fn(S) -> R>,
 }
 
+// The state of a message transfer, kept in the channel so that the transfer can be resumed if the
+// future performing it is dropped or `Poll::Pending` is returned.
+#[cfg(unix)]
+type Sending = SingleObjectSender;
+#[cfg(unix)]
+type Receiving<T> = SingleObjectReceiver<T>;
+
+#[cfg(windows)]
+struct Sending {
+    // The length of the message followed by the message itself.
+    buffer: Vec<u8>,
+    pos: usize,
+    // For tracing.
+    type_name: fn() -> &'static str,
+    handles: usize,
+}
+
+#[cfg(windows)]
+impl Sending {
+    fn is_unstarted(&self) -> bool {
+        self.pos == 0
+    }
+}
+
+#[cfg(windows)]
+struct Receiving<T> {
+    len: [u8; size_of::<usize>()],
+    len_pos: usize,
+    data: Vec<u8>,
+    data_pos: usize,
+    marker: PhantomData<fn() -> T>,
+}
+
+#[cfg(windows)]
+impl<T> Receiving<T> {
+    fn is_unstarted(&self) -> bool {
+        self.len_pos == 0
+    }
+}
+
+// `Stream` is only needed to choose the blocking mode on Unix.
+#[cfg_attr(windows, allow(clippy::extra_unused_type_parameters))]
+fn start_send<Stream: AsyncStream, T: Object>(value: T) -> Result<Box<Sending>> {
+    #[cfg(unix)]
+    {
+        Ok(Box::new(SingleObjectSender::new(
+            value,
+            Stream::IS_BLOCKING,
+        )?))
+    }
+    #[cfg(windows)]
+    {
+        let (serialized, handles) = serialize_with_handles(value)?;
+        let mut buffer = serialized.len().to_ne_bytes().to_vec();
+        buffer.extend(serialized);
+        Ok(Box::new(Sending {
+            buffer,
+            pos: 0,
+            type_name: crate::trace::type_name::<T>,
+            handles,
+        }))
+    }
+}
+
+// Continue sending the pending message, if any.
+fn poll_send<Stream: AsyncStream>(
+    fd: &mut Stream,
+    sending: &mut Option<Box<Sending>>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<()>> {
+    let Some(message) = sending else {
+        return Poll::Ready(Ok(()));
+    };
+    #[cfg(unix)]
+    let result = {
+        let fd = &*fd;
+        ready!(fd.poll_blocking_write(cx, || message.send_next(fd.as_fd())))
+    };
+    #[cfg(windows)]
+    let result = loop {
+        if message.pos == message.buffer.len() {
+            if crate::trace::is_enabled() {
+                crate::trace::message(
+                    &crate::trace::channel_id(fd.as_socket()),
+                    crate::trace::Direction::Send,
+                    (message.type_name)(),
+                    &message.buffer[size_of::<usize>()..],
+                    message.handles,
+                    None,
+                );
+            }
+            break Ok(());
+        }
+        match ready!(fd.poll_write(cx, &message.buffer[message.pos..])) {
+            Ok(0) => break Err(ErrorKind::WriteZero.into()),
+            Ok(n) => message.pos += n,
+            Err(e) => break Err(e),
+        }
+    };
+    // Blocking streams switched to non-blocking I/O by a timeout, see `blocking::poll_until`.
+    if matches!(&result, Err(e) if e.kind() == ErrorKind::WouldBlock) {
+        return Poll::Pending;
+    }
+    *sending = None;
+    Poll::Ready(result)
+}
+
+// Continue receiving a message, starting a new one if necessary.
+fn poll_recv<Stream: AsyncStream, T: Object>(
+    fd: &mut Stream,
+    receiving: &mut Option<Box<Receiving<T>>>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<Option<Serializer>>> {
+    #[cfg(unix)]
+    let result = {
+        let message = receiving.get_or_insert_with(|| {
+            Box::new(unsafe { SingleObjectReceiver::new(Stream::IS_BLOCKING) })
+        });
+        let fd = &*fd;
+        ready!(fd.poll_blocking_read(cx, || message.recv_next(fd.as_fd())))
+    };
+    #[cfg(windows)]
+    let result = {
+        let message = receiving.get_or_insert_with(|| {
+            Box::new(Receiving {
+                len: [0; size_of::<usize>()],
+                len_pos: 0,
+                data: Vec::new(),
+                data_pos: 0,
+                marker: PhantomData,
+            })
+        });
+        loop {
+            if message.len_pos < message.len.len() {
+                // The stream may only end between messages.
+                match ready!(fd.poll_read(cx, &mut message.len[message.len_pos..])) {
+                    Ok(0) if message.len_pos == 0 => break Ok(trace_closed::<T>(fd)),
+                    Ok(0) => break Err(ErrorKind::UnexpectedEof.into()),
+                    Ok(n) => {
+                        message.len_pos += n;
+                        if message.len_pos == message.len.len() {
+                            message.data = vec![0; usize::from_ne_bytes(message.len)];
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::ConnectionReset && message.len_pos == 0 => {
+                        break Ok(trace_closed::<T>(fd));
+                    }
+                    Err(e) => break Err(e),
+                }
+            } else if message.data_pos < message.data.len() {
+                match ready!(fd.poll_read(cx, &mut message.data[message.data_pos..])) {
+                    Ok(0) => break Err(ErrorKind::UnexpectedEof.into()),
+                    Ok(n) => message.data_pos += n,
+                    Err(e) => break Err(e),
+                }
+            } else {
+                let data = std::mem::take(&mut message.data);
+                // Keep the data around for tracing, since it's consumed by deserialization.
+                let traced = crate::trace::is_enabled().then(|| data.clone());
+                let result = unsafe { deserialize_with_handles(data) };
+                if let (Some(data), Ok(s)) = (traced, &result) {
+                    crate::trace::message(
+                        &crate::trace::channel_id(fd.as_socket()),
+                        crate::trace::Direction::Recv,
+                        crate::trace::type_name::<T>(),
+                        &data,
+                        s.handles.len() + s.sockets.len(),
+                        None,
+                    );
+                }
+                break result.map(Some);
+            }
+        }
+    };
+    // Blocking streams switched to non-blocking I/O by a timeout, see `blocking::poll_until`.
+    if matches!(&result, Err(e) if e.kind() == ErrorKind::WouldBlock) {
+        return Poll::Pending;
+    }
+    *receiving = None;
+    Poll::Ready(result)
+}
+
+// Log that the peer has closed the channel and return the end of stream.
+#[cfg(windows)]
+fn trace_closed<T>(fd: &impl AsSocket) -> Option<Serializer> {
+    if crate::trace::is_enabled() {
+        crate::trace::closed(
+            &crate::trace::channel_id(fd.as_socket()),
+            crate::trace::type_name::<T>(),
+        );
+    }
+    None
+}
+
+#[cfg(unix)]
+fn set_receiving_blocking<Stream: AsyncStream, T: Object>(
+    receiving: &mut Option<Box<Receiving<T>>>,
+    blocking: bool,
+) {
+    receiving
+        .get_or_insert_with(|| Box::new(unsafe { SingleObjectReceiver::new(Stream::IS_BLOCKING) }))
+        .set_blocking(blocking);
+}
+
 /// Create a unidirectional channel.
 pub fn channel<Stream: AsyncStream, T: Object>() -> Result<(Sender<Stream, T>, Receiver<Stream, T>)>
 {
@@ -159,27 +417,151 @@ pub fn duplex<Stream: AsyncStream, A: Object, B: Object>()
     }
 }
 
+// Open another stream to the same socket, so that it can be read and written to concurrently.
+pub(crate) fn try_clone_stream<Stream: AsyncStream>(stream: &Stream) -> Result<Stream> {
+    #[cfg(unix)]
+    let stream = SyncStream::from(stream.as_fd().try_clone_to_owned()?);
+    #[cfg(windows)]
+    let stream = SyncStream::from(stream.as_socket().try_clone_to_owned()?);
+    Stream::try_new(stream)
+}
+
 impl<Stream: AsyncStream, T: Object> Sender<Stream, T> {
     pub(crate) unsafe fn from_stream(fd: Stream) -> Self {
         Sender {
             fd,
+            sending: None,
             marker: PhantomData,
         }
     }
 
     /// Send a value to the other side.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancel-safe: if the future is dropped before it completes, the channel can
+    /// still be used. The value may have been partially sent by then; the rest of it is kept in
+    /// the sender and sent by the next call to `send` or [`flush`](Self::flush), so the other side
+    /// always receives whole messages. If the sender is dropped before that, the other side fails
+    /// to receive the message.
+    ///
+    /// The leftover message is not transferred together with the sender, so call `flush` before
+    /// passing the sender to another process. Passing a sender with a leftover message fails with an
+    /// error of kind [`InvalidInput`](ErrorKind::InvalidInput).
     pub async fn send(&mut self, value: T) -> Result<()> {
+        self.flush().await?;
+        self.begin_send(value)?;
+        self.flush().await
+    }
+
+    /// Finish sending the message left over by a cancelled [`send`](Self::send), if any.
+    pub async fn flush(&mut self) -> Result<()> {
+        poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    // Make `value` the message to be sent by `poll_flush`. The previous one must have been flushed.
+    pub(crate) fn begin_send(&mut self, value: T) -> Result<()> {
+        assert!(
+            self.sending.is_none(),
+            "A message is started before the previous one is flushed",
+        );
+        self.sending = Some(start_send::<Stream, T>(value)?);
+        Ok(())
+    }
+
+    pub(crate) fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        poll_send(&mut self.fd, &mut self.sending, cx)
+    }
+
+    // Switch the message in progress between blocking and non-blocking I/O, see
+    // `blocking::poll_until`.
+    #[cfg(unix)]
+    pub(crate) fn set_blocking(&mut self, blocking: bool) {
+        if let Some(message) = &mut self.sending {
+            message.set_blocking(blocking);
+        }
+    }
+
+    // Drop the message in progress if no part of it has been sent yet. Returns whether it was
+    // dropped.
+    pub(crate) fn discard_unstarted(&mut self) -> bool {
+        let unstarted = self
+            .sending
+            .as_ref()
+            .is_some_and(|message| message.is_unstarted());
+        if unstarted {
+            self.sending = None;
+        }
+        unstarted
+    }
+
+    /// Send a value to the other side unless the channel is full, in which case the value is
+    /// returned back. Only supported by blocking streams.
+    pub(crate) async fn send_nonblocking(&mut self, value: T) -> Result<Option<T>> {
+        // A message left over by a timeout has to be sent first.
+        self.flush().await?;
         #[cfg(unix)]
         {
-            let mut sender = SingleObjectSender::new(self.fd.as_fd(), value, Stream::IS_BLOCKING);
-            self.fd.blocking_write(|| sender.send_next()).await
+            let mut sender = SingleObjectSender::new(value, Stream::IS_BLOCKING)?;
+            sender.start_nonblocking();
+            let fd = self.fd.as_fd();
+            match self.fd.blocking_write(|| sender.send_next(fd)).await {
+                Err(e) if e.kind() == ErrorKind::WouldBlock && sender.is_unstarted() => {
+                    Ok(Some(unsafe { sender.into_value() }))
+                }
+                result => result.map(|()| None),
+            }
         }
         #[cfg(windows)]
         {
-            let serialized = serialize_with_handles(value)?;
-            self.fd.write(&serialized.len().to_ne_bytes()).await?;
-            self.fd.write(&serialized).await
+            if !crate::internals::is_ready(self.fd.as_socket(), true)? {
+                return Ok(Some(value));
+            }
+            self.send(value).await.map(|()| None)
+        }
+    }
+}
+
+unsafe impl<Stream: AsyncStream, T: Object> Object for Sender<Stream, T> {
+    fn serialize_self(self, s: &mut Serializer) {
+        if self.sending.is_some() {
+            s.fail(Error::new(
+                ErrorKind::InvalidInput,
+                "A sender with a message that hasn't been flushed cannot be passed to another \
+                 process",
+            ));
         }
+        s.serialize(self.fd);
+    }
+    unsafe fn deserialize_self(d: &mut Deserializer) -> Self {
+        unsafe { Self::from_stream(d.deserialize()) }
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<Stream: AsyncStream + Unpin, T: Object> futures_sink::Sink<T> for Sender<Stream, T> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_flush(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<()> {
+        let this = self.get_mut();
+        assert!(
+            this.sending.is_none(),
+            "Sink::start_send called without Sink::poll_ready",
+        );
+        this.sending = Some(start_send::<Stream, T>(item)?);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_flush(cx)
     }
 }
 
@@ -213,6 +595,7 @@ impl<Stream: AsyncStream, T: Object> Receiver<Stream, T> {
     pub(crate) unsafe fn from_stream(fd: Stream) -> Self {
         Receiver {
             fd,
+            receiving: None,
             marker: PhantomData,
         }
     }
@@ -220,28 +603,91 @@ impl<Stream: AsyncStream, T: Object> Receiver<Stream, T> {
     /// Receive a value from the other side.
     ///
     /// Returns `Ok(None)` if the other side has dropped the channel.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancel-safe: if the future is dropped before it completes, no message is
+    /// lost. The part of the message received so far is kept in the receiver, and the next call to
+    /// `recv` continues from there.
+    ///
+    /// The partially received message is not transferred together with the receiver, so `recv`
+    /// has to complete before the receiver is passed to another process. Passing a receiver with a
+    /// partially received message fails with an error of kind
+    /// [`InvalidInput`](ErrorKind::InvalidInput).
     pub async fn recv(&mut self) -> Result<Option<T>> {
-        #[cfg(unix)]
-        {
-            let mut receiver =
-                unsafe { SingleObjectReceiver::new(self.fd.as_fd(), Stream::IS_BLOCKING) };
-            self.fd.blocking_read(|| receiver.recv_next()).await
-        }
-        #[cfg(windows)]
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    // Switch the message in progress between blocking and non-blocking I/O, see
+    // `blocking::poll_until`.
+    #[cfg(unix)]
+    pub(crate) fn set_blocking(&mut self, blocking: bool) {
+        set_receiving_blocking::<Stream, T>(&mut self.receiving, blocking);
+    }
+
+    pub(crate) fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<T>>> {
+        let serialized = ready!(poll_recv(&mut self.fd, &mut self.receiving, cx))?;
+        Poll::Ready(Ok(serialized.map(|serialized| unsafe {
+            Deserializer::from(serialized).deserialize()
+        })))
+    }
+
+    /// Receive a value from the other side without deserializing it.
+    ///
+    /// If `nonblocking` is set, fails with `WouldBlock` if no message is available. This is only
+    /// supported by blocking streams.
+    pub(crate) async fn recv_serialized(
+        &mut self,
+        nonblocking: bool,
+    ) -> Result<Option<Serializer>> {
+        // A message partially received before a timeout is finished as usual.
+        if nonblocking
+            && self
+                .receiving
+                .as_ref()
+                .is_none_or(|message| message.is_unstarted())
         {
-            let mut len = [0u8; size_of::<usize>()];
-            if let Err(e) = self.fd.read(&mut len).await {
-                if let ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset = e.kind() {
-                    return Ok(None);
-                }
-                return Err(e);
+            #[cfg(unix)]
+            {
+                let mut receiver = unsafe { SingleObjectReceiver::<T>::new(Stream::IS_BLOCKING) };
+                receiver.start_nonblocking();
+                let fd = self.fd.as_fd();
+                return self.fd.blocking_read(|| receiver.recv_next(fd)).await;
+            }
+            #[cfg(windows)]
+            if !crate::internals::is_ready(self.fd.as_socket(), false)? {
+                return Err(ErrorKind::WouldBlock.into());
             }
-            let len = usize::from_ne_bytes(len);
+        }
+        poll_fn(|cx| poll_recv(&mut self.fd, &mut self.receiving, cx)).await
+    }
+}
 
-            let mut serialized = vec![0u8; len];
-            self.fd.read(&mut serialized).await?;
-            unsafe { deserialize_with_handles(serialized) }.map(Some)
+unsafe impl<Stream: AsyncStream, T: Object> Object for Receiver<Stream, T> {
+    fn serialize_self(self, s: &mut Serializer) {
+        if !self
+            .receiving
+            .as_ref()
+            .is_none_or(|message| message.is_unstarted())
+        {
+            s.fail(Error::new(
+                ErrorKind::InvalidInput,
+                "A receiver with a partially received message cannot be passed to another process",
+            ));
         }
+        s.serialize(self.fd);
+    }
+    unsafe fn deserialize_self(d: &mut Deserializer) -> Self {
+        unsafe { Self::from_stream(d.deserialize()) }
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<Stream: AsyncStream + Unpin, T: Object> futures_core::Stream for Receiver<Stream, T> {
+    type Item = Result<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<T>>> {
+        self.get_mut().poll_recv(cx).map(Result::transpose)
     }
 }
 
@@ -275,55 +721,130 @@ impl<Stream: AsyncStream, S: Object, R: Object> Duplex<Stream, S, R> {
     pub(crate) unsafe fn from_stream(fd: Stream) -> Self {
         Duplex {
             fd,
+            sending: None,
+            receiving: None,
             marker: PhantomData,
         }
     }
 
     /// Send a value to the other side.
+    ///
+    /// This method is cancel-safe in the same way as [`Sender::send`].
     pub async fn send(&mut self, value: S) -> Result<()> {
+        self.flush().await?;
+        self.begin_send(value)?;
+        self.flush().await
+    }
+
+    /// Finish sending the message left over by a cancelled [`send`](Self::send), if any.
+    pub async fn flush(&mut self) -> Result<()> {
+        poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    // Make `value` the message to be sent by `poll_flush`. The previous one must have been flushed.
+    pub(crate) fn begin_send(&mut self, value: S) -> Result<()> {
+        assert!(
+            self.sending.is_none(),
+            "A message is started before the previous one is flushed",
+        );
+        self.sending = Some(start_send::<Stream, S>(value)?);
+        Ok(())
+    }
+
+    pub(crate) fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        poll_send(&mut self.fd, &mut self.sending, cx)
+    }
+
+    // Switch the messages in progress between blocking and non-blocking I/O, see
+    // `blocking::poll_until`.
+    #[cfg(unix)]
+    pub(crate) fn set_blocking(&mut self, blocking: bool) {
+        if let Some(message) = &mut self.sending {
+            message.set_blocking(blocking);
+        }
+        set_receiving_blocking::<Stream, R>(&mut self.receiving, blocking);
+    }
+
+    /// Send a value to the other side unless the channel is full, in which case the value is
+    /// returned back. Only supported by blocking streams.
+    pub(crate) async fn send_nonblocking(&mut self, value: S) -> Result<Option<S>> {
+        // A message left over by a timeout has to be sent first.
+        self.flush().await?;
         #[cfg(unix)]
         {
-            let mut sender = SingleObjectSender::new(self.fd.as_fd(), value, Stream::IS_BLOCKING);
-            self.fd.blocking_write(|| sender.send_next()).await
+            let mut sender = SingleObjectSender::new(value, Stream::IS_BLOCKING)?;
+            sender.start_nonblocking();
+            let fd = self.fd.as_fd();
+            match self.fd.blocking_write(|| sender.send_next(fd)).await {
+                Err(e) if e.kind() == ErrorKind::WouldBlock && sender.is_unstarted() => {
+                    Ok(Some(unsafe { sender.into_value() }))
+                }
+                result => result.map(|()| None),
+            }
         }
         #[cfg(windows)]
         {
-            let serialized = serialize_with_handles(value)?;
-            self.fd.write(&serialized.len().to_ne_bytes()).await?;
-            self.fd.write(&serialized).await
+            if !crate::internals::is_ready(self.fd.as_socket(), true)? {
+                return Ok(Some(value));
+            }
+            self.send(value).await.map(|()| None)
         }
     }
 
     /// Receive a value from the other side.
     ///
     /// Returns `Ok(None)` if the other side has dropped the channel.
+    ///
+    /// This method is cancel-safe in the same way as [`Receiver::recv`].
     pub async fn recv(&mut self) -> Result<Option<R>> {
-        #[cfg(unix)]
-        {
-            let mut receiver =
-                unsafe { SingleObjectReceiver::new(self.fd.as_fd(), Stream::IS_BLOCKING) };
-            self.fd.blocking_read(|| receiver.recv_next()).await
-        }
-        #[cfg(windows)]
+        let serialized = self.recv_serialized(false).await?;
+        Ok(serialized.map(|serialized| unsafe { Deserializer::from(serialized).deserialize() }))
+    }
+
+    pub(crate) fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<R>>> {
+        let serialized = ready!(poll_recv(&mut self.fd, &mut self.receiving, cx))?;
+        Poll::Ready(Ok(serialized.map(|serialized| unsafe {
+            Deserializer::from(serialized).deserialize()
+        })))
+    }
+
+    /// Receive a value from the other side without deserializing it.
+    ///
+    /// If `nonblocking` is set, fails with `WouldBlock` if no message is available. This is only
+    /// supported by blocking streams.
+    pub(crate) async fn recv_serialized(
+        &mut self,
+        nonblocking: bool,
+    ) -> Result<Option<Serializer>> {
+        // A message partially received before a timeout is finished as usual.
+        if nonblocking
+            && self
+                .receiving
+                .as_ref()
+                .is_none_or(|message| message.is_unstarted())
         {
-            let mut len = [0u8; size_of::<usize>()];
-            if let Err(e) = self.fd.read(&mut len).await {
-                if let ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset = e.kind() {
-                    return Ok(None);
-                }
-                return Err(e);
+            #[cfg(unix)]
+            {
+                let mut receiver = unsafe { SingleObjectReceiver::<R>::new(Stream::IS_BLOCKING) };
+                receiver.start_nonblocking();
+                let fd = self.fd.as_fd();
+                return self.fd.blocking_read(|| receiver.recv_next(fd)).await;
+            }
+            #[cfg(windows)]
+            if !crate::internals::is_ready(self.fd.as_socket(), false)? {
+                return Err(ErrorKind::WouldBlock.into());
             }
-            let len = usize::from_ne_bytes(len);
-
-            let mut serialized = vec![0u8; len];
-            self.fd.read(&mut serialized).await?;
-            unsafe { deserialize_with_handles(serialized) }.map(Some)
         }
+        poll_fn(|cx| poll_recv(&mut self.fd, &mut self.receiving, cx)).await
     }
 
     /// Send a value from the other side and wait for a response immediately.
     ///
     /// If the other side closes the channel before responding, an error is returned.
+    ///
+    /// Cancelling the request after the value starts being sent doesn't cancel the request on the
+    /// other side, so the response to it is received by the next call to [`recv`](Self::recv) or
+    /// `request`.
     pub async fn request(&mut self, value: S) -> Result<R> {
         self.send(value).await?;
         self.recv().await?.ok_or_else(|| {
@@ -335,11 +856,212 @@ impl<Stream: AsyncStream, S: Object, R: Object> Duplex<Stream, S, R> {
     }
 
     pub fn into_sender(self) -> Sender<Stream, S> {
-        unsafe { Sender::from_stream(self.fd) }
+        Sender {
+            sending: self.sending,
+            ..unsafe { Sender::from_stream(self.fd) }
+        }
     }
 
     pub fn into_receiver(self) -> Receiver<Stream, R> {
-        unsafe { Receiver::from_stream(self.fd) }
+        Receiver {
+            receiving: self.receiving,
+            ..unsafe { Receiver::from_stream(self.fd) }
+        }
+    }
+
+    /// Split the duplex into a sending half and a receiving half.
+    ///
+    /// Unlike the duplex, the halves can be used from different tasks, so that one task can
+    /// receive messages while another one is sending. Use [`SendHalf::reunite`] or
+    /// [`RecvHalf::reunite`] to put them back together.
+    ///
+    /// This duplicates the underlying file descriptor (or socket, on Windows), which may fail. The
+    /// duplex is returned back along with the error in this case.
+    pub fn split(
+        mut self,
+    ) -> std::result::Result<(SendHalf<Stream, S>, RecvHalf<Stream, R>), (Error, Self)> {
+        let fd = match try_clone_stream(&self.fd) {
+            Ok(fd) => fd,
+            Err(e) => return Err((e, self)),
+        };
+        let receiver = Receiver {
+            receiving: self.receiving.take(),
+            ..unsafe { Receiver::from_stream(fd) }
+        };
+        let pair = Arc::new(());
+        Ok((
+            SendHalf {
+                sender: self.into_sender(),
+                pair: pair.clone(),
+            },
+            RecvHalf { receiver, pair },
+        ))
+    }
+}
+
+/// The sending half of a [`Duplex`], created by [`Duplex::split`].
+pub struct SendHalf<Stream: AsyncStream, S: Object> {
+    sender: Sender<Stream, S>,
+    // Shared with the other half.
+    pair: Arc<()>,
+}
+
+/// The receiving half of a [`Duplex`], created by [`Duplex::split`].
+pub struct RecvHalf<Stream: AsyncStream, R: Object> {
+    receiver: Receiver<Stream, R>,
+    // Shared with the other half.
+    pair: Arc<()>,
+}
+
+/// The error returned when trying to reunite halves of different duplexes.
+///
+/// The halves are returned back.
+pub struct ReuniteError<Stream: AsyncStream, S: Object, R: Object>(
+    pub SendHalf<Stream, S>,
+    pub RecvHalf<Stream, R>,
+);
+
+fn reunite<Stream: AsyncStream, S: Object, R: Object>(
+    send_half: SendHalf<Stream, S>,
+    recv_half: RecvHalf<Stream, R>,
+) -> std::result::Result<Duplex<Stream, S, R>, ReuniteError<Stream, S, R>> {
+    if !Arc::ptr_eq(&send_half.pair, &recv_half.pair) {
+        return Err(ReuniteError(send_half, recv_half));
+    }
+    // The duplicated stream is closed.
+    Ok(Duplex {
+        sending: send_half.sender.sending,
+        receiving: recv_half.receiver.receiving,
+        ..unsafe { Duplex::from_stream(send_half.sender.fd) }
+    })
+}
+
+impl<Stream: AsyncStream, S: Object> SendHalf<Stream, S> {
+    /// Send a value to the other side.
+    ///
+    /// This method is cancel-safe in the same way as [`Sender::send`].
+    pub async fn send(&mut self, value: S) -> Result<()> {
+        self.sender.send(value).await
+    }
+
+    /// Finish sending the message left over by a cancelled [`send`](Self::send), if any.
+    pub async fn flush(&mut self) -> Result<()> {
+        self.sender.flush().await
+    }
+
+    /// Put the duplex back together.
+    ///
+    /// Fails if `other` comes from a different duplex.
+    pub fn reunite<R: Object>(
+        self,
+        other: RecvHalf<Stream, R>,
+    ) -> std::result::Result<Duplex<Stream, S, R>, ReuniteError<Stream, S, R>> {
+        reunite(self, other)
+    }
+}
+
+impl<Stream: AsyncStream, R: Object> RecvHalf<Stream, R> {
+    /// Receive a value from the other side.
+    ///
+    /// Returns `Ok(None)` if the other side has dropped the channel.
+    ///
+    /// This method is cancel-safe in the same way as [`Receiver::recv`].
+    pub async fn recv(&mut self) -> Result<Option<R>> {
+        self.receiver.recv().await
+    }
+
+    /// Put the duplex back together.
+    ///
+    /// Fails if `other` comes from a different duplex.
+    pub fn reunite<S: Object>(
+        self,
+        other: SendHalf<Stream, S>,
+    ) -> std::result::Result<Duplex<Stream, S, R>, ReuniteError<Stream, S, R>> {
+        reunite(other, self)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<Stream: AsyncStream + Unpin, S: Object> futures_sink::Sink<S> for SendHalf<Stream, S> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        futures_sink::Sink::poll_ready(Pin::new(&mut self.get_mut().sender), cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: S) -> Result<()> {
+        futures_sink::Sink::start_send(Pin::new(&mut self.get_mut().sender), item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        futures_sink::Sink::poll_flush(Pin::new(&mut self.get_mut().sender), cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        futures_sink::Sink::poll_close(Pin::new(&mut self.get_mut().sender), cx)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<Stream: AsyncStream + Unpin, R: Object> futures_core::Stream for RecvHalf<Stream, R> {
+    type Item = Result<R>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<R>>> {
+        futures_core::Stream::poll_next(Pin::new(&mut self.get_mut().receiver), cx)
+    }
+}
+
+impl<Stream: AsyncStream + fmt::Debug, S: Object> fmt::Debug for SendHalf<Stream, S> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_tuple("SendHalf").field(&self.sender.fd).finish()
+    }
+}
+
+impl<Stream: AsyncStream + fmt::Debug, R: Object> fmt::Debug for RecvHalf<Stream, R> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_tuple("RecvHalf")
+            .field(&self.receiver.fd)
+            .finish()
+    }
+}
+
+impl<Stream: AsyncStream, S: Object, R: Object> fmt::Debug for ReuniteError<Stream, S, R> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_tuple("ReuniteError").finish_non_exhaustive()
+    }
+}
+
+impl<Stream: AsyncStream, S: Object, R: Object> fmt::Display for ReuniteError<Stream, S, R> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Tried to reunite halves of different duplexes")
+    }
+}
+
+impl<Stream: AsyncStream, S: Object, R: Object> std::error::Error for ReuniteError<Stream, S, R> {}
+
+unsafe impl<Stream: AsyncStream, S: Object, R: Object> Object for Duplex<Stream, S, R> {
+    fn serialize_self(self, s: &mut Serializer) {
+        if self.sending.is_some() {
+            s.fail(Error::new(
+                ErrorKind::InvalidInput,
+                "A duplex with a message that hasn't been flushed cannot be passed to another \
+                 process",
+            ));
+        }
+        if !self
+            .receiving
+            .as_ref()
+            .is_none_or(|message| message.is_unstarted())
+        {
+            s.fail(Error::new(
+                ErrorKind::InvalidInput,
+                "A duplex with a partially received message cannot be passed to another process",
+            ));
+        }
+        s.serialize(self.fd);
+    }
+    unsafe fn deserialize_self(d: &mut Deserializer) -> Self {
+        unsafe { Self::from_stream(d.deserialize()) }
     }
 }
 
@@ -381,16 +1103,27 @@ pub(crate) type ProcID = rustix::process::RawPid;
 #[cfg(windows)]
 pub(crate) type ProcID = HANDLE;
 
+// Where the function runs: in a subprocess or, in in-process mode, on a thread.
+#[derive(Debug)]
+pub(crate) enum Runner {
+    Process(ProcHandle),
+    Thread(std::thread::JoinHandle<()>),
+}
+
 /// A subprocess.
 pub struct Child<Stream: AsyncStream, T: Object> {
-    pub(crate) proc_handle: ProcHandle,
+    pub(crate) runner: Runner,
     output_rx: Receiver<Stream, T>,
     may_kill: Arc<Mutex<bool>>,
+    // Closed when the thread forwarding the child's log records finishes.
+    #[cfg(feature = "log")]
+    log_forwarder: Option<Receiver<Stream, ()>>,
 }
 
 /// A handle that allows to kill the process.
 pub struct KillHandle {
-    proc_id: ProcID,
+    // `None` if the function runs in-process.
+    proc_id: Option<ProcID>,
     may_kill: Arc<Mutex<bool>>,
 }
 
@@ -398,31 +1131,72 @@ unsafe impl Send for KillHandle {}
 unsafe impl Sync for KillHandle {}
 
 impl<Stream: AsyncStream, T: Object> Child<Stream, T> {
-    fn new(proc_handle: ProcHandle, output_rx: Receiver<Stream, T>) -> Child<Stream, T> {
+    fn new(runner: Runner, output_rx: Receiver<Stream, T>) -> Child<Stream, T> {
         Child {
-            proc_handle,
+            runner,
             output_rx,
             may_kill: Arc::new(Mutex::new(true)),
+            #[cfg(feature = "log")]
+            log_forwarder: None,
         }
     }
 
+    // Wait for the child to report that it has applied spawn options. If that fails, the child is
+    // reaped and the error is returned.
+    async fn wait_for_setup(self) -> Result<Self> {
+        let mut status_rx = unsafe {
+            Receiver::<Stream, core::result::Result<(), SetupError>>::from_stream(self.output_rx.fd)
+        };
+        let status = status_rx.recv().await;
+        let child = Child {
+            runner: self.runner,
+            output_rx: unsafe { Receiver::from_stream(status_rx.fd) },
+            may_kill: self.may_kill,
+            #[cfg(feature = "log")]
+            log_forwarder: self.log_forwarder,
+        };
+        let err = match status {
+            Ok(Some(Ok(()))) => return Ok(child),
+            Ok(Some(Err(err))) => err.into(),
+            Ok(None) => Error::other("The subprocess exited during setup"),
+            Err(err) => err,
+        };
+        // The child exits on its own on setup failure, but it might be stuck if the channel broke.
+        let _ = child.get_kill_handle().kill();
+        let _ = child.join().await;
+        Err(err)
+    }
+
+    // The stream the child's output arrives on. It becomes readable when the function returns or
+    // the child exits.
+    pub(crate) fn output_stream(&self) -> &Stream {
+        &self.output_rx.fd
+    }
+
     /// Get a handle for process termination.
     pub fn get_kill_handle(&self) -> crate::KillHandle {
         KillHandle {
-            proc_id: self.id(),
+            proc_id: match self.runner {
+                Runner::Process(_) => Some(self.id()),
+                Runner::Thread(_) => None,
+            },
             may_kill: self.may_kill.clone(),
         }
     }
 
     /// Get ID of the process.
+    ///
+    /// In [in-process mode](crate::set_in_process), this is the ID of the current process.
     pub fn id(&self) -> ProcID {
         #[cfg(unix)]
-        {
-            rustix::process::Pid::as_raw(Some(self.proc_handle))
+        match self.runner {
+            Runner::Process(pid) => rustix::process::Pid::as_raw(Some(pid)),
+            Runner::Thread(_) => rustix::process::Pid::as_raw(Some(rustix::process::getpid())),
         }
         #[cfg(windows)]
-        {
-            HANDLE(self.proc_handle.as_raw_handle())
+        match self.runner {
+            Runner::Process(ref handle) => HANDLE(handle.as_raw_handle()),
+            Runner::Thread(_) => unsafe { Threading::GetCurrentProcess() },
         }
     }
 
@@ -438,16 +1212,32 @@ impl<Stream: AsyncStream, T: Object> Child<Stream, T> {
             // in `spawn` for more detail. This read effectively transmutes `()` to `T`.
             value = Some(unsafe { std::ptr::dangling::<T>().read() });
         }
+        // The forwarder finishes when the child exits and closes its end of the channel. Waiting
+        // for it guarantees that the child's records are logged before we return.
+        #[cfg(feature = "log")]
+        if let Some(mut forwarder) = self.log_forwarder.take() {
+            let _ = forwarder.recv().await;
+        }
         let mut guard = self.may_kill.lock().expect("Kill mutex is poisoned");
         *guard = false;
+        let proc_handle = match self.runner {
+            Runner::Process(proc_handle) => proc_handle,
+            Runner::Thread(thread) => {
+                // The thread has closed the channel, so it's about to finish.
+                return match thread.join() {
+                    Ok(()) => value.ok_or_else(|| {
+                        Error::other("The function terminated without returning a value")
+                    }),
+                    Err(_) => Err(Error::other("The function panicked")),
+                };
+            }
+        };
         // This is synchronous, but should be really fast
         #[cfg(unix)]
         {
-            let (_pid, status) = rustix::process::waitpid(
-                Some(self.proc_handle),
-                rustix::process::WaitOptions::empty(),
-            )?
-            .unwrap();
+            let (_pid, status) =
+                rustix::process::waitpid(Some(proc_handle), rustix::process::WaitOptions::empty())?
+                    .unwrap();
             if status.exit_status() == Some(0) {
                 value.ok_or_else(|| {
                     Error::other("The subprocess terminated without returning a value")
@@ -462,7 +1252,7 @@ impl<Stream: AsyncStream, T: Object> Child<Stream, T> {
         {
             if unsafe {
                 Threading::WaitForSingleObject(
-                    HANDLE(self.proc_handle.as_raw_handle()),
+                    HANDLE(proc_handle.as_raw_handle()),
                     Threading::INFINITE,
                 )
             }
@@ -473,7 +1263,7 @@ impl<Stream: AsyncStream, T: Object> Child<Stream, T> {
             let mut code: u32 = 0;
             unsafe {
                 Threading::GetExitCodeProcess(
-                    HANDLE(self.proc_handle.as_raw_handle()),
+                    HANDLE(proc_handle.as_raw_handle()),
                     &mut code as *mut u32,
                 )?;
             }
@@ -493,7 +1283,7 @@ impl<Stream: AsyncStream, T: Object> Child<Stream, T> {
 impl<Stream: AsyncStream + fmt::Debug, T: Object> fmt::Debug for Child<Stream, T> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("Child")
-            .field("proc_handle", &self.proc_handle)
+            .field("runner", &self.runner)
             .field("output_rx", &self.output_rx)
             .finish()
     }
@@ -508,14 +1298,20 @@ impl KillHandle {
                 "This process has already been joined",
             ));
         }
+        let Some(proc_id) = self.proc_id else {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Functions running in-process cannot be killed",
+            ));
+        };
         #[cfg(unix)]
         rustix::process::kill_process(
-            rustix::process::Pid::from_raw(self.proc_id).unwrap(),
+            rustix::process::Pid::from_raw(proc_id).unwrap(),
             rustix::process::Signal::KILL,
         )?;
         #[cfg(windows)]
         unsafe {
-            Threading::TerminateProcess(self.proc_id, 1)?;
+            Threading::TerminateProcess(proc_id, 1)?;
         }
         Ok(())
     }
@@ -536,6 +1332,8 @@ pub(crate) async unsafe fn spawn<
     Ret: Object,
 >(
     _func: Func,
+    function: &'static str,
+    options: &SpawnOptions,
     args: Args,
 ) -> Result<Child<Stream, Ret>> {
     unsafe {
@@ -573,6 +1371,42 @@ pub(crate) async unsafe fn spawn<
         let (local, child) = crate::duplex()?;
         let mut local: Duplex<Stream, _, Ret> = local.try_into()?;
 
+        #[cfg(feature = "tracing")]
+        let context = crate::tracing::SpanContext::current();
+        #[cfg(not(feature = "tracing"))]
+        let context = ();
+
+        if imp::is_in_process() {
+            let child = child.0.fd.0.into();
+            #[cfg(feature = "tracing")]
+            let span = ::tracing::Span::current();
+            let thread = std::thread::Builder::new()
+                .name("crossmist".to_string())
+                .spawn(move || {
+                    // Nest the child span under the parent's one.
+                    #[cfg(feature = "tracing")]
+                    let _guard = span.entered();
+                    run_entry(child, false)
+                })?;
+            // Records are logged by the parent's logger directly.
+            #[cfg(feature = "log")]
+            let log_forwarding: Option<crate::log_forwarding::LogForwarding> = None;
+            #[cfg(not(feature = "log"))]
+            let log_forwarding = ();
+            local
+                .send(Entry((
+                    entrypoint,
+                    function.to_string(),
+                    options.clone(),
+                    context,
+                    log_forwarding,
+                    args,
+                )))
+                .await?;
+            let receiver = Receiver::from_stream(local.fd);
+            return Ok(Child::new(Runner::Thread(thread), receiver));
+        }
+
         let process_handle;
 
         // Send fds/handles/sockets via a channel instead of inheritance, because:
@@ -582,7 +1416,7 @@ pub(crate) async unsafe fn spawn<
         //   them via the broker anyway.
         #[cfg(unix)]
         {
-            process_handle = subprocess::_spawn_child(child.0.fd.as_fd())?;
+            process_handle = subprocess::_spawn_child(child.0.fd.as_fd(), options)?;
         }
 
         #[cfg(windows)]
@@ -594,10 +1428,50 @@ pub(crate) async unsafe fn spawn<
             local.fd = signal.fd;
         }
 
-        local.send((entrypoint, args)).await?;
+        #[cfg(feature = "log")]
+        let (log_forwarding, log_forwarder) = {
+            #[cfg(unix)]
+            let pid = process_handle.as_raw_nonzero().get() as u32;
+            #[cfg(windows)]
+            let pid = Threading::GetProcessId(HANDLE(process_handle.as_raw_handle()));
+            crate::log_forwarding::start(function, pid)?.unzip()
+        };
+        #[cfg(not(feature = "log"))]
+        let log_forwarding = ();
+
+        let sent = local
+            .send(Entry((
+                entrypoint,
+                function.to_string(),
+                options.clone(),
+                context,
+                log_forwarding,
+                args,
+            )))
+            .await;
+
+        // Drop our copy of the child's end of the channel so that we notice if it dies early.
+        drop(child);
 
         let receiver = Receiver::from_stream(local.fd);
-        Ok(Child::new(process_handle, receiver))
+        #[allow(unused_mut)]
+        let mut child = Child::new(Runner::Process(process_handle), receiver);
+        #[cfg(feature = "log")]
+        if let Some(done) = log_forwarder {
+            child.log_forwarder = Some(Receiver::from_stream(Stream::try_new(done.0.fd.0)?));
+        }
+        if let Err(err) = sent {
+            // The child is waiting for an entry it will never get, e.g. because the arguments
+            // couldn't be serialized.
+            let _ = child.get_kill_handle().kill();
+            let _ = child.join().await;
+            return Err(err);
+        }
+        if options.needs_child_setup() {
+            child.wait_for_setup().await
+        } else {
+            Ok(child)
+        }
     }
 }
 
@@ -605,6 +1479,42 @@ pub(crate) fn handle_entry(
     #[cfg(unix)] channel: OwnedFd,
     #[cfg(windows)] channel: OwnedSocket,
 ) -> ! {
+    run_entry(channel, true);
+    std::process::exit(0);
+}
+
+// Whether `CROSSMIST_DEBUG_WAIT` lists the function, either by name or by path.
+fn debug_wait_requested(function: &str) -> bool {
+    let Some(functions) = std::env::var_os("CROSSMIST_DEBUG_WAIT") else {
+        return false;
+    };
+    let name = function.rsplit("::").next().unwrap_or(function);
+    functions
+        .to_string_lossy()
+        .split(',')
+        .any(|pattern| pattern == "*" || pattern == name || pattern == function)
+}
+
+// The message that starts a child. It's wrapped so that traces can show it as such instead of
+// the types it consists of, see `trace::type_name`.
+pub(crate) struct Entry<T>(T);
+
+unsafe impl<T: Object> Object for Entry<T> {
+    fn serialize_self(self, s: &mut Serializer) {
+        s.serialize(self.0);
+    }
+    unsafe fn deserialize_self(d: &mut Deserializer) -> Self {
+        Self(unsafe { d.deserialize() })
+    }
+}
+
+// Receive the entry message and invoke the function. In in-process mode, this is called on a
+// thread of the parent, so process-wide setup is skipped.
+fn run_entry(
+    #[cfg(unix)] channel: OwnedFd,
+    #[cfg(windows)] channel: OwnedSocket,
+    in_subprocess: bool,
+) {
     // XXX: very hacky
     struct FakeDeserializer(Deserializer);
     unsafe impl Object for FakeDeserializer {
@@ -624,14 +1534,58 @@ pub(crate) fn handle_entry(
     #[cfg(windows)]
     let mut rx = unsafe { crate::Receiver::from_raw_socket(channel.as_raw_socket()) };
 
-    let deserializer: FakeDeserializer = rx
-        .recv()
-        .expect("failed to read entry for crossmist")
-        .expect("no entry passed");
-    let mut deserializer = deserializer.0;
+    let entry = rx.recv().expect("failed to read entry for crossmist");
     core::mem::forget(rx);
+    // The parent closes the channel without sending the entry if it fails to serialize it.
+    let Some(Entry(FakeDeserializer(mut deserializer))) = entry else {
+        return;
+    };
 
     let entry: StaticFn<fn(_, _)> = unsafe { deserializer.deserialize() };
+    let function: String = unsafe { deserializer.deserialize() };
+    let options: SpawnOptions = unsafe { deserializer.deserialize() };
+    #[cfg(feature = "tracing")]
+    let context: crate::tracing::SpanContext = unsafe { deserializer.deserialize() };
+    #[cfg(not(feature = "tracing"))]
+    let () = unsafe { deserializer.deserialize() };
+    #[cfg(feature = "log")]
+    let log_forwarding: Option<crate::log_forwarding::LogForwarding> =
+        unsafe { deserializer.deserialize() };
+    #[cfg(not(feature = "log"))]
+    let () = unsafe { deserializer.deserialize() };
+
+    if in_subprocess {
+        if options.wait_for_debugger || debug_wait_requested(&function) {
+            eprintln!(
+                "crossmist: process {} running {function} is waiting for a debugger to attach",
+                std::process::id(),
+            );
+            setup::wait_for_debugger();
+        }
+        #[cfg(feature = "log")]
+        if let Some(log_forwarding) = log_forwarding {
+            crate::log_forwarding::install(log_forwarding);
+        }
+        imp::run_child_start_hooks();
+    }
+
+    if in_subprocess && options.needs_child_setup() {
+        let status = setup::apply(&options).map_err(SetupError::from);
+        let failed = status.is_err();
+        #[cfg(unix)]
+        let mut tx = unsafe { crate::Sender::from_raw_fd(channel.as_raw_fd()) };
+        #[cfg(windows)]
+        let mut tx = unsafe { crate::Sender::from_raw_socket(channel.as_raw_socket()) };
+        tx.send(status)
+            .expect("Failed to report subprocess setup status");
+        core::mem::forget(tx);
+        if failed {
+            std::process::exit(1);
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    let _guard = context.child_span(&function).entered();
+
     (entry.get_fn())(deserializer, channel);
-    std::process::exit(0);
 }
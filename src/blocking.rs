@@ -46,9 +46,9 @@
 //! You can then kill the child, get its PID, or join it (i.e. wait till it returns and obtain the
 //! returned value).
 
-use crate::{KillHandle, Object, asynchronous};
+use crate::{Deserializer, KillHandle, Object, Serializer, SpawnOptions, asynchronous};
 use std::future::Future;
-use std::io::Result;
+use std::io::{ErrorKind, Result};
 #[cfg(unix)]
 use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, RawFd};
 #[cfg(windows)]
@@ -57,8 +57,9 @@ use std::os::windows::io::{
 };
 use std::pin::pin;
 use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
 
-fn block_on<F: Future>(f: F) -> F::Output {
+pub(crate) fn block_on<F: Future>(f: F) -> F::Output {
     let mut cx = Context::from_waker(Waker::noop());
     match pin!(f).poll(&mut cx) {
         Poll::Ready(value) => value,
@@ -66,6 +67,97 @@ fn block_on<F: Future>(f: F) -> F::Output {
     }
 }
 
+// A channel whose transfers can be switched to non-blocking I/O.
+trait Channel {
+    fn stream(&self) -> &Blocking;
+    fn set_blocking(&mut self, blocking: bool) -> Result<()>;
+}
+
+macro_rules! impl_channel {
+    ($($ty:ident<$($param:ident),*>),*) => {
+        $(
+            impl<$($param: Object),*> Channel for asynchronous::$ty<Blocking, $($param),*> {
+                fn stream(&self) -> &Blocking {
+                    &self.fd
+                }
+
+                fn set_blocking(&mut self, blocking: bool) -> Result<()> {
+                    #[cfg(unix)]
+                    {
+                        asynchronous::$ty::set_blocking(self, blocking);
+                        Ok(())
+                    }
+                    // Unlike on Unix, the mode of a Windows socket is not shared with other
+                    // processes, so it can be switched directly.
+                    #[cfg(windows)]
+                    self.fd.0.set_nonblocking(!blocking)
+                }
+            }
+        )*
+    };
+}
+
+impl_channel!(Sender<T>, Receiver<T>, Duplex<S, R>);
+
+// Blocking I/O can't be interrupted at a deadline, so drive the transfer with non-blocking I/O,
+// waiting for the stream to become ready in between, until it completes or fails with `TimedOut`
+// at the deadline. The transfer is kept in the channel on timeout, and the next call resumes it.
+fn poll_until<C: Channel, T>(
+    chan: &mut C,
+    write: bool,
+    deadline: Instant,
+    mut poll: impl FnMut(&mut C, &mut Context<'_>) -> Poll<Result<T>>,
+) -> Result<T> {
+    chan.set_blocking(false)?;
+    let mut cx = Context::from_waker(Waker::noop());
+    let result = loop {
+        if let Poll::Ready(result) = poll(chan, &mut cx) {
+            break result;
+        }
+        #[cfg(unix)]
+        let ready = crate::internals::wait_ready(chan.stream().as_fd(), write, deadline);
+        #[cfg(windows)]
+        let ready = crate::internals::wait_ready(chan.stream().as_socket(), write, deadline);
+        if let Err(e) = ready {
+            break Err(e);
+        }
+    };
+    chan.set_blocking(true)?;
+    result
+}
+
+// Convert the result of a non-blocking receive to `TryRecv`.
+fn try_recv_result<T: Object>(result: Result<Option<Serializer>>) -> Result<TryRecv<T>> {
+    match result {
+        Ok(Some(serialized)) => Ok(TryRecv::Value(unsafe {
+            Deserializer::from(serialized).deserialize()
+        })),
+        Ok(None) => Ok(TryRecv::Closed),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(TryRecv::Empty),
+        Err(e) => Err(e),
+    }
+}
+
+/// The outcome of [`Receiver::try_recv`] and [`Duplex::try_recv`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecv<T> {
+    /// A value has been received.
+    Value(T),
+    /// No message is available at the moment.
+    Empty,
+    /// The other side has dropped the channel.
+    Closed,
+}
+
+/// The outcome of [`Sender::try_send`] and [`Duplex::try_send`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySend<T> {
+    /// The value has been sent.
+    Sent,
+    /// The channel has no room for the value at the moment. The value is returned back.
+    Full(T),
+}
+
 /// Synchronous implementation marker type.
 #[derive(Debug, Object)]
 pub struct Blocking(pub(crate) asynchronous::SyncStream);
@@ -97,6 +189,34 @@ unsafe impl asynchronous::AsyncStream for Blocking {
         use std::io::Read;
         self.0.read_exact(buf)
     }
+
+    #[cfg(unix)]
+    fn poll_blocking_write<T>(
+        &self,
+        _cx: &mut Context<'_>,
+        mut f: impl FnMut() -> Result<T>,
+    ) -> Poll<Result<T>> {
+        Poll::Ready(f())
+    }
+    #[cfg(windows)]
+    fn poll_write(&mut self, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        use std::io::Write;
+        Poll::Ready(self.0.write(buf))
+    }
+
+    #[cfg(unix)]
+    fn poll_blocking_read<T>(
+        &self,
+        _cx: &mut Context<'_>,
+        mut f: impl FnMut() -> Result<T>,
+    ) -> Poll<Result<T>> {
+        Poll::Ready(f())
+    }
+    #[cfg(windows)]
+    fn poll_read(&mut self, _cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        use std::io::Read;
+        Poll::Ready(self.0.read(buf))
+    }
 }
 
 #[cfg(unix)]
@@ -187,6 +307,72 @@ impl<T: Object> Sender<T> {
     pub fn send(&mut self, value: T) -> Result<()> {
         block_on(self.0.send(value))
     }
+
+    /// Send a value to the other side, giving up after `timeout`.
+    ///
+    /// If the timeout expires, an error of kind [`TimedOut`](std::io::ErrorKind::TimedOut) is
+    /// returned. The value may have been partially sent by then; the rest of it is kept in the
+    /// sender and sent by the next call to `send`, `send_timeout`, `try_send`, or
+    /// [`flush`](Self::flush), so the other side always receives whole messages. If a message left
+    /// over by a previous call can't be sent in time, `value` is dropped without being sent.
+    pub fn send_timeout(&mut self, value: T, timeout: Duration) -> Result<()> {
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return self.send(value);
+        };
+        poll_until(&mut self.0, true, deadline, |chan, cx| chan.poll_flush(cx))?;
+        self.0.begin_send(value)?;
+        poll_until(&mut self.0, true, deadline, |chan, cx| chan.poll_flush(cx))
+    }
+
+    /// Finish sending the message left over by [`send_timeout`](Self::send_timeout), if any.
+    ///
+    /// The sender cannot be passed to another process until this is done.
+    pub fn flush(&mut self) -> Result<()> {
+        block_on(self.0.flush())
+    }
+
+    // Send as much of the message in progress as fits in the channel without blocking. Returns
+    // whether it has been sent in full.
+    pub(crate) fn try_flush(&mut self) -> Result<bool> {
+        Channel::set_blocking(&mut self.0, false)?;
+        let result = self.0.poll_flush(&mut Context::from_waker(Waker::noop()));
+        Channel::set_blocking(&mut self.0, true)?;
+        match result {
+            Poll::Ready(result) => result.map(|()| true),
+            Poll::Pending => Ok(false),
+        }
+    }
+
+    // Like `try_send`, but never blocks: the part of the message that doesn't fit in the channel
+    // is kept for `try_flush`. If a previous message is still in progress or the channel is full,
+    // `value` is dropped and `false` is returned.
+    pub(crate) fn try_start_send(&mut self, value: T) -> Result<bool> {
+        if !self.try_flush()? {
+            return Ok(false);
+        }
+        self.0.begin_send(value)?;
+        self.try_flush()?;
+        Ok(!self.0.discard_unstarted())
+    }
+
+    // Duplicate the socket. Messages sent via the copies may interleave, see `mpsc` for a safe
+    // wrapper.
+    pub(crate) fn try_clone(&self) -> Result<Self> {
+        let fd = Blocking(self.0.fd.0.try_clone()?);
+        Ok(Self(unsafe { asynchronous::Sender::from_stream(fd) }))
+    }
+
+    /// Send a value to the other side if the channel has room for it, without blocking.
+    ///
+    /// If the channel is full, [`TrySend::Full`] is returned with the value, which can be retried
+    /// later. Once a message starts being sent, it's sent in full, so this method may block if the
+    /// message is larger than the room left in the channel.
+    pub fn try_send(&mut self, value: T) -> Result<TrySend<T>> {
+        Ok(match block_on(self.0.send_nonblocking(value))? {
+            Some(value) => TrySend::Full(value),
+            None => TrySend::Sent,
+        })
+    }
 }
 
 #[cfg(unix)]
@@ -239,6 +425,47 @@ impl<T: Object> Receiver<T> {
     pub fn recv(&mut self) -> Result<Option<T>> {
         block_on(self.0.recv())
     }
+
+    /// Receive a value from the other side, giving up after `timeout`.
+    ///
+    /// If the timeout expires, an error of kind [`TimedOut`](std::io::ErrorKind::TimedOut) is
+    /// returned and the channel can be used as usual afterwards. The part of the message received
+    /// so far is kept in the receiver, and the next call to `recv`, `recv_timeout`, or `try_recv`
+    /// continues from there. The receiver cannot be passed to another process until then.
+    ///
+    /// Returns `Ok(None)` if the other side has dropped the channel.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<T>> {
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return self.recv();
+        };
+        poll_until(&mut self.0, false, deadline, |chan, cx| chan.poll_recv(cx))
+    }
+
+    // Duplicate the socket. Messages are delivered to whichever copy reads them first.
+    pub(crate) fn try_clone(&self) -> Result<Self> {
+        let fd = Blocking(self.0.fd.0.try_clone()?);
+        Ok(Self(unsafe { asynchronous::Receiver::from_stream(fd) }))
+    }
+
+    /// Receive a value from the other side if one is available, without blocking.
+    ///
+    /// Returns [`TryRecv::Empty`] if no message has arrived yet and [`TryRecv::Closed`] if the
+    /// other side has dropped the channel. Once a message starts arriving, it's received in full,
+    /// so this method may block until the rest of a large message is sent.
+    pub fn try_recv(&mut self) -> Result<TryRecv<T>> {
+        try_recv_result(block_on(self.0.recv_serialized(true)))
+    }
+}
+
+/// Receives values until the other side drops the channel.
+///
+/// Errors are yielded as is and don't stop the iteration.
+impl<T: Object> Iterator for Receiver<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        self.recv().transpose()
+    }
 }
 
 #[cfg(unix)]
@@ -294,6 +521,38 @@ impl<S: Object, R: Object> Duplex<S, R> {
         block_on(self.0.send(value))
     }
 
+    /// Send a value to the other side, giving up after `timeout`.
+    ///
+    /// This method behaves like [`Sender::send_timeout`].
+    pub fn send_timeout(&mut self, value: S, timeout: Duration) -> Result<()> {
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return self.send(value);
+        };
+        poll_until(&mut self.0, true, deadline, |chan, cx| chan.poll_flush(cx))?;
+        self.0.begin_send(value)?;
+        poll_until(&mut self.0, true, deadline, |chan, cx| chan.poll_flush(cx))
+    }
+
+    /// Finish sending the message left over by [`send_timeout`](Self::send_timeout) or
+    /// [`request_timeout`](Self::request_timeout), if any.
+    ///
+    /// The duplex cannot be passed to another process until this is done.
+    pub fn flush(&mut self) -> Result<()> {
+        block_on(self.0.flush())
+    }
+
+    /// Send a value to the other side if the channel has room for it, without blocking.
+    ///
+    /// If the channel is full, [`TrySend::Full`] is returned with the value, which can be retried
+    /// later. Once a message starts being sent, it's sent in full, so this method may block if the
+    /// message is larger than the room left in the channel.
+    pub fn try_send(&mut self, value: S) -> Result<TrySend<S>> {
+        Ok(match block_on(self.0.send_nonblocking(value))? {
+            Some(value) => TrySend::Full(value),
+            None => TrySend::Sent,
+        })
+    }
+
     /// Receive a value from the other side.
     ///
     /// Returns `Ok(None)` if the other side has dropped the channel.
@@ -301,6 +560,27 @@ impl<S: Object, R: Object> Duplex<S, R> {
         block_on(self.0.recv())
     }
 
+    /// Receive a value from the other side, giving up after `timeout`.
+    ///
+    /// This method behaves like [`Receiver::recv_timeout`].
+    ///
+    /// Returns `Ok(None)` if the other side has dropped the channel.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<R>> {
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return self.recv();
+        };
+        poll_until(&mut self.0, false, deadline, |chan, cx| chan.poll_recv(cx))
+    }
+
+    /// Receive a value from the other side if one is available, without blocking.
+    ///
+    /// Returns [`TryRecv::Empty`] if no message has arrived yet and [`TryRecv::Closed`] if the
+    /// other side has dropped the channel. Once a message starts arriving, it's received in full,
+    /// so this method may block until the rest of a large message is sent.
+    pub fn try_recv(&mut self) -> Result<TryRecv<R>> {
+        try_recv_result(block_on(self.0.recv_serialized(true)))
+    }
+
     /// Send a value from the other side and wait for a response immediately.
     ///
     /// If the other side closes the channel before responding, an error is returned.
@@ -308,6 +588,29 @@ impl<S: Object, R: Object> Duplex<S, R> {
         block_on(self.0.request(value))
     }
 
+    /// Send a value from the other side and wait for a response, giving up after `timeout`.
+    ///
+    /// The timeout covers both sending the request and receiving the response, with the same
+    /// guarantees as [`send_timeout`](Self::send_timeout) and [`recv_timeout`](Self::recv_timeout).
+    /// Note that if the request has been sent, the response may still arrive after the timeout, and
+    /// will then be returned by the next call to [`recv`](Self::recv) or [`request`](Self::request).
+    ///
+    /// If the other side closes the channel before responding, an error is returned.
+    pub fn request_timeout(&mut self, value: S, timeout: Duration) -> Result<R> {
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return self.request(value);
+        };
+        poll_until(&mut self.0, true, deadline, |chan, cx| chan.poll_flush(cx))?;
+        self.0.begin_send(value)?;
+        poll_until(&mut self.0, true, deadline, |chan, cx| chan.poll_flush(cx))?;
+        poll_until(&mut self.0, false, deadline, |chan, cx| chan.poll_recv(cx))?.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "The subprocess exitted before responding to the request",
+            )
+        })
+    }
+
     pub fn into_sender(self) -> Sender<S> {
         Sender(self.0.into_sender())
     }
@@ -388,7 +691,14 @@ impl<T: Object> Child<T> {
 #[doc(hidden)]
 pub unsafe fn spawn<Func: FnOnce(Box<dyn FnOnce() -> Args>) -> Ret, Args: Object, Ret: Object>(
     func: Func,
+    function: &'static str,
+    options: &SpawnOptions,
     args: Args,
 ) -> Result<Child<Ret>> {
-    unsafe { block_on(asynchronous::spawn::<Blocking, _, _, _>(func, args)).map(Child) }
+    unsafe {
+        block_on(asynchronous::spawn::<Blocking, _, _, _>(
+            func, function, options, args,
+        ))
+        .map(Child)
+    }
 }
@@ -0,0 +1,186 @@
+//! Broadcast channels.
+//!
+//! A [`Publisher`] created by [`broadcast`] sends each message to every [`Subscriber`]. Subscribers
+//! are objects, so they can be passed to children:
+//!
+//! ```standalone_crate
+//! use crossmist::broadcast::{Subscriber, broadcast};
+//!
+//! #[crossmist::func]
+//! fn worker(mut config: Subscriber<String>) -> Vec<String> {
+//!     let mut updates = Vec::new();
+//!     while let Some(update) = config.recv().unwrap() {
+//!         updates.push(update);
+//!     }
+//!     updates
+//! }
+//!
+//! fn main() {
+//!     crossmist::init();
+//!
+//!     let mut publisher = broadcast::<String>();
+//!     let children: Vec<_> = (0..3)
+//!         .map(|_| worker.spawn(publisher.subscribe().unwrap()).unwrap())
+//!         .collect();
+//!     publisher.publish("verbose=1".to_string());
+//!     publisher.publish("verbose=2".to_string());
+//!     drop(publisher);
+//!
+//!     for child in children {
+//!         assert_eq!(child.join().unwrap(), ["verbose=1", "verbose=2"]);
+//!     }
+//! }
+//! ```
+//!
+//! Each subscriber has its own buffer. The publisher never waits for a subscriber to make room in
+//! its buffer: if a subscriber is too slow and its buffer is full, the message is not delivered to
+//! it, and [`publish`](Publisher::publish) reports the subscriber as lagging, so that the caller
+//! can [`unsubscribe`](Publisher::unsubscribe) it or deal with it otherwise.
+//!
+//! A message that only partially fits in a subscriber's buffer is not dropped: the rest of it is
+//! sent by later calls to `publish` as the subscriber makes room, and messages published in the
+//! meantime are missed by that subscriber. [`flush`](Publisher::flush) waits until such messages
+//! are delivered, which should be done before dropping the publisher or unsubscribing, as otherwise
+//! the subscriber receives an error instead of the incomplete message.
+
+use crate::{Object, Receiver, Sender, TryRecv};
+use std::io::Result;
+use std::time::Duration;
+
+/// An identifier of a subscriber, unique within its publisher.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Object)]
+pub struct SubscriberId(u64);
+
+/// The sending side of a broadcast channel.
+#[derive(Debug)]
+pub struct Publisher<T: Object> {
+    subscribers: Vec<(SubscriberId, Sender<T>)>,
+    next_id: u64,
+}
+
+/// The receiving side of a broadcast channel.
+#[derive(Debug, Object)]
+pub struct Subscriber<T: Object> {
+    id: SubscriberId,
+    receiver: Receiver<T>,
+}
+
+/// The outcome of [`Publisher::publish`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Published {
+    /// Subscribers whose buffers were full or that are still receiving an earlier message. They
+    /// didn't receive the message, but they are still subscribed.
+    pub lagging: Vec<SubscriberId>,
+    /// Subscribers that have been dropped or failed. They are unsubscribed.
+    pub disconnected: Vec<SubscriberId>,
+}
+
+/// Create a broadcast channel without subscribers.
+pub fn broadcast<T: Object>() -> Publisher<T> {
+    Publisher {
+        subscribers: Vec::new(),
+        next_id: 0,
+    }
+}
+
+impl<T: Object> Publisher<T> {
+    /// Add a subscriber.
+    ///
+    /// The subscriber receives messages published after this call.
+    pub fn subscribe(&mut self) -> Result<Subscriber<T>> {
+        let (sender, receiver) = crate::channel()?;
+        let id = SubscriberId(self.next_id);
+        self.next_id += 1;
+        self.subscribers.push((id, sender));
+        Ok(Subscriber { id, receiver })
+    }
+
+    /// Remove a subscriber.
+    ///
+    /// The subscriber receives the messages that are already in its buffer and then `None`, or an
+    /// error if a message was only partially sent to it, see [`flush`](Self::flush). Returns
+    /// `false` if the subscriber has already been removed.
+    pub fn unsubscribe(&mut self, id: SubscriberId) -> bool {
+        let len = self.subscribers.len();
+        self.subscribers.retain(|(subscriber, _)| *subscriber != id);
+        self.subscribers.len() != len
+    }
+
+    /// The identifiers of the current subscribers.
+    pub fn subscribers(&self) -> impl Iterator<Item = SubscriberId> + '_ {
+        self.subscribers.iter().map(|(id, _)| *id)
+    }
+
+    /// Send a value to all subscribers without waiting for slow ones.
+    ///
+    /// Subscribers that have been dropped are unsubscribed automatically. Both them and the
+    /// subscribers that missed the message because their buffers were full are listed in the
+    /// result.
+    pub fn publish(&mut self, value: T) -> Published
+    where
+        T: Clone,
+    {
+        let mut published = Published::default();
+        self.subscribers
+            .retain_mut(|(id, sender)| match sender.try_start_send(value.clone()) {
+                Ok(true) => true,
+                Ok(false) => {
+                    published.lagging.push(*id);
+                    true
+                }
+                Err(_) => {
+                    published.disconnected.push(*id);
+                    false
+                }
+            });
+        published
+    }
+
+    /// Wait until the messages that didn't fit in the subscribers' buffers are delivered in full.
+    ///
+    /// Subscribers that have been dropped are unsubscribed automatically and returned.
+    pub fn flush(&mut self) -> Vec<SubscriberId> {
+        let mut disconnected = Vec::new();
+        self.subscribers.retain_mut(|(id, sender)| {
+            let ok = sender.flush().is_ok();
+            if !ok {
+                disconnected.push(*id);
+            }
+            ok
+        });
+        disconnected
+    }
+}
+
+impl<T: Object> Subscriber<T> {
+    /// The identifier of this subscriber.
+    pub fn id(&self) -> SubscriberId {
+        self.id
+    }
+
+    /// Receive the next published value.
+    ///
+    /// Returns `Ok(None)` if the publisher has been dropped or has unsubscribed this subscriber.
+    pub fn recv(&mut self) -> Result<Option<T>> {
+        self.receiver.recv()
+    }
+
+    /// Receive the next published value, waiting at most `timeout` for it to arrive.
+    ///
+    /// See [`Receiver::recv_timeout`] for details.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<T>> {
+        self.receiver.recv_timeout(timeout)
+    }
+
+    /// Receive the next published value if one is available, without blocking.
+    ///
+    /// See [`Receiver::try_recv`] for details.
+    pub fn try_recv(&mut self) -> Result<TryRecv<T>> {
+        self.receiver.try_recv()
+    }
+
+    /// Convert the subscriber into the underlying receiver.
+    pub fn into_receiver(self) -> Receiver<T> {
+        self.receiver
+    }
+}
@@ -1,9 +1,15 @@
 #[cfg(feature = "smol")]
 pub use async_io;
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{
+    Once, OnceLock,
+    atomic::{AtomicBool, Ordering},
+};
 
 static INITIALIZED: AtomicBool = AtomicBool::new(false);
+static CHILD_START_HOOKS: OnceLock<Vec<fn()>> = OnceLock::new();
+static TEST_NAME: OnceLock<String> = OnceLock::new();
+static IN_PROCESS: AtomicBool = AtomicBool::new(false);
 
 pub(crate) fn perform_sanity_checks() {
     assert!(
@@ -14,13 +20,15 @@ pub(crate) fn perform_sanity_checks() {
 
 /// Initialize the crossmist runtime.
 ///
-/// This function should always be called at the beginning of `main`.
+/// This function should always be called at the beginning of `main`, unless `main` is annotated
+/// with [`#[crossmist::main]`](macro@crate::main).
 ///
 /// When crossmist spawns child processes, they start executing the same `main` function as the root
 /// process. Calling [`init`] lets crossmist pass control to the function that the process is
 /// actually supposed to be executing.
 ///
-/// In asynchronous programs, avoid annotating `main` with `#[tokio::main]` directly, and prefer:
+/// In asynchronous programs, avoid annotating `main` with `#[tokio::main]` directly, and prefer
+/// `#[crossmist::main(tokio)]` or, equivalently:
 ///
 /// ```rust
 /// fn main() {
@@ -39,18 +47,189 @@ pub(crate) fn perform_sanity_checks() {
 /// user code to recursively re-executing the same program instead of running a function. Using
 /// `crossmist` from tests requires [a custom harness](https://www.unwoundstack.com/blog/integration-testing-rust-binaries.html)
 /// with a global setup hook calling [`init`].
+///
+/// This is equivalent to `Init::new().init()`. Use [`Init`] to run code on process startup.
 pub fn init() {
-    if INITIALIZED.swap(true, Ordering::Relaxed) {
-        panic!("crossmist::init() is called twice");
+    Init::new().init();
+}
+
+/// Builder for initializing the crossmist runtime with hooks.
+///
+/// Hooks are useful for initializing process-wide state, like loggers, tracing subscribers, and
+/// metrics, which is not inherited by child processes.
+///
+/// ```standalone_crate
+/// #[crossmist::func]
+/// fn child() {
+///     println!("Hello from the child");
+/// }
+///
+/// fn main() {
+///     crossmist::Init::new()
+///         .on_child_start(|| eprintln!("Child {} started", std::process::id()))
+///         .on_parent_start(|| eprintln!("Root process started"))
+///         .init();
+///     child.run().unwrap();
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct Init {
+    child_start_hooks: Vec<fn()>,
+    parent_start_hooks: Vec<fn()>,
+}
+
+impl Init {
+    /// Create a builder without hooks.
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    let mut args = std::env::args();
-    if args.next().as_deref() == Some("_crossmist_") {
-        crate::entry::crossmist_main(args);
+    /// Register a hook to run in each child process before the function is invoked.
+    ///
+    /// Hooks run in the order they were registered, before arguments are deserialized and before
+    /// [`SpawnOptions`](crate::SpawnOptions) are applied, so they can rely on the privileges of the
+    /// parent.
+    pub fn on_child_start(mut self, hook: fn()) -> Self {
+        self.child_start_hooks.push(hook);
+        self
+    }
+
+    /// Register a hook to run in the root process, i.e. when `main` is invoked not by crossmist.
+    ///
+    /// Hooks run in the order they were registered, before [`Init::init`] returns.
+    pub fn on_parent_start(mut self, hook: fn()) -> Self {
+        self.parent_start_hooks.push(hook);
+        self
+    }
+
+    /// Initialize the crossmist runtime.
+    ///
+    /// See [`init`] for more information.
+    pub fn init(self) {
+        if INITIALIZED.swap(true, Ordering::Relaxed) {
+            panic!("crossmist::init() is called twice");
+        }
+
+        // Children are usually started with `_crossmist_` as `argv[0]`, but a wrapper command
+        // passes the path to the executable first. The parent only sets `CROSSMIST_WRAPPED` in the
+        // latter case, so that a program run with `_crossmist_` as its first argument by the user
+        // is not mistaken for a child. The variable is removed from the environment of the
+        // processes the child spawns in turn.
+        let args: Vec<String> = std::env::args().collect();
+        let marker = if std::env::var_os("CROSSMIST_WRAPPED").is_some() {
+            1
+        } else {
+            0
+        };
+        if args.get(marker).is_some_and(|arg| arg == "_crossmist_") {
+            CHILD_START_HOOKS
+                .set(self.child_start_hooks)
+                .expect("Child start hooks are already set");
+            crate::entry::crossmist_main(args.into_iter().skip(marker + 1));
+        }
+
+        #[cfg(windows)]
+        crate::subprocess::start_broker().expect("failed to start broker");
+
+        read_in_process_from_env();
+
+        for hook in self.parent_start_hooks {
+            hook();
+        }
+    }
+}
+
+/// Initialize the crossmist runtime from a test. Used by `#[crossmist::test]`.
+///
+/// Unlike [`init`], this function can be called several times. When called in a child process, it
+/// never returns.
+pub fn init_test(module_path: &str, name: &str) {
+    // Test names don't include the crate name.
+    let test_name = match module_path.split_once("::") {
+        Some((_, path)) => format!("{path}::{name}"),
+        None => name.to_string(),
+    };
+    let _ = TEST_NAME.set(test_name);
+
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        // Child processes are started with a `_crossmist_=<fd>,<flags...>` argument, which libtest
+        // interprets as a filter that matches no tests.
+        if let Some(token) = std::env::args().nth(1)
+            && let Some(token) = token.strip_prefix("_crossmist_")
+        {
+            INITIALIZED.store(true, Ordering::Relaxed);
+            let args = token.strip_prefix('=').unwrap_or("").split(',');
+            let args = args.filter(|arg| !arg.is_empty()).map(String::from);
+            // A panic escaping the function would otherwise be caught by libtest, which would
+            // report a test failure instead of letting the process die. The panic message has
+            // already been printed by the hook at this point.
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                crate::entry::crossmist_main(args)
+            }));
+            std::process::exit(101);
+        }
+
+        if INITIALIZED.swap(true, Ordering::Relaxed) {
+            panic!("crossmist::init() and #[crossmist::test] cannot be used together");
+        }
+
+        #[cfg(windows)]
+        crate::subprocess::start_broker().expect("failed to start broker");
+
+        read_in_process_from_env();
+    });
+}
+
+/// Run functions on threads of the current process instead of in subprocesses.
+///
+/// This is a debugging aid: it lets you step through the code of functions started with `spawn`,
+/// `run`, `spawn_tokio`, and similar methods in a debugger attached to a single process. Arguments,
+/// return values, and messages are still passed through real channels, so serialization works
+/// exactly as with subprocesses.
+///
+/// In-process mode can also be enabled by setting the environment variable `CROSSMIST_INPROCESS`
+/// to `1` before [`init`] is called.
+///
+/// In this mode, [`SpawnOptions`](crate::SpawnOptions) and [`Init::on_child_start`] hooks are
+/// ignored, since they would affect the whole process. Functions can't be killed, and the function
+/// calling [`std::process::exit`] terminates the whole program.
+///
+/// ```standalone_crate
+/// #[crossmist::func]
+/// fn pid() -> u32 {
+///     std::process::id()
+/// }
+///
+/// fn main() {
+///     crossmist::init();
+///     crossmist::set_in_process(true);
+///     assert_eq!(pid.run().unwrap(), std::process::id());
+/// }
+/// ```
+pub fn set_in_process(enabled: bool) {
+    IN_PROCESS.store(enabled, Ordering::Relaxed);
+}
+
+fn read_in_process_from_env() {
+    if std::env::var_os("CROSSMIST_INPROCESS").is_some_and(|value| value == "1") {
+        set_in_process(true);
     }
+}
+
+pub(crate) fn is_in_process() -> bool {
+    IN_PROCESS.load(Ordering::Relaxed)
+}
 
-    #[cfg(windows)]
-    crate::subprocess::start_broker().expect("failed to start broker");
+// The name of a test that can dispatch to crossmist when running under the libtest harness.
+pub(crate) fn current_test_name() -> Option<&'static str> {
+    TEST_NAME.get().map(|name| name.as_str())
+}
+
+pub(crate) fn run_child_start_hooks() {
+    for hook in CHILD_START_HOOKS.get().into_iter().flatten() {
+        hook();
+    }
 }
 
 #[cfg(feature = "tokio")]
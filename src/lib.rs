@@ -113,11 +113,37 @@
 //! ```
 //!
 //!
+//! # Debugging
+//!
+//! A few environment variables help to find out what children are doing:
+//! - `CROSSMIST_TRACE=1` logs every message sent or received over a channel to stderr, together
+//!   with the channel, the type of the message, its size, and the first 32 bytes of its serialized
+//!   data in hex.
+//! - `CROSSMIST_DEBUG_WAIT` makes children wait for a debugger, see
+//!   [`SpawnOptions::wait_for_debugger`].
+//! - `CROSSMIST_WRAPPER` starts children under a command like `strace -f`, see
+//!   [`SpawnOptions::wrapper`]. This is only supported on Unix-like systems.
+//! - `CROSSMIST_INPROCESS=1` runs functions on threads instead of processes, see
+//!   [`set_in_process`].
+//!
+//! To reproduce a bug that depends on the messages a child receives, record them with
+//! [`replay::Recorder`] and feed them back with [`replay::Replay`].
+//!
+//!
 //! # Features
 //!
 //! This crate provides the following features:
 //! - `tokio`: enable [Tokio](https://tokio.rs) async runtime support.
 //! - `smol`: enable [smol](https://crates.io/crates/smol) async runtime support.
+//! - `tracing`: propagate [tracing](https://crates.io/crates/tracing) span context to children, see
+//!   [`tracing`](mod@tracing).
+//! - `log`: forward records logged by children via the [log](https://crates.io/crates/log) crate to
+//!   the parent's logger. The target of each forwarded record is prefixed with the function name
+//!   and the process ID of the child, e.g. `my_crate::worker[1234]::my_crate::db`. This takes effect
+//!   if the parent has enabled logging by the time the child is spawned and the child doesn't set
+//!   its own logger.
+//! - `futures`: implement [`Stream`](futures_core::Stream) for asynchronous receivers and
+//!   [`Sink`](futures_sink::Sink) for asynchronous senders.
 //! - `nightly`: make use of nightly features. This enables crossmist to be more performant and
 //!   provide better API, but requires a nightly compiler to be used.
 
@@ -164,6 +190,16 @@ extern crate self as crossmist;
 /// combines the two operations into one, which may be useful if a new process is needed for a
 /// reason other than parallel execution.
 ///
+/// The methods `spawn_with` and `run_with` are similar, but take [`SpawnOptions`] as the first
+/// argument, allowing the child process to be configured before the function starts:
+///
+/// ```ignore
+/// pub fn spawn_with(&self, options: &crossmist::SpawnOptions, arg1: Type1, ...) ->
+///     std::io::Result<crossmist::Child<Output>>;
+/// pub fn run_with(&self, options: &crossmist::SpawnOptions, arg1: Type1, ...) ->
+///     std::io::Result<Output>;
+/// ```
+///
 /// For example:
 ///
 /// ```standalone_crate
@@ -313,7 +349,8 @@ extern crate self as crossmist;
 /// ```
 ///
 /// If `smol` is enabled, the functions `spawn_smol` and `run_smol` with matching signatures are
-/// generated.
+/// generated. `spawn_tokio_with`, `run_tokio_with`, `spawn_smol_with`, and `run_smol_with` take
+/// [`SpawnOptions`] just like their synchronous counterparts.
 ///
 /// Additionally, the function may be `async`. In this case, you have to indicate which runtime to
 /// use as follows:
@@ -371,6 +408,170 @@ extern crate self as crossmist;
 /// ```
 pub use crossmist_derive::func;
 
+/// Mark the entrypoint of the program.
+///
+/// This attribute inserts a call to [`init`] at the beginning of `main`:
+///
+/// ```standalone_crate
+/// #[crossmist::func]
+/// fn example(a: i32, b: i32) -> i32 {
+///     a + b
+/// }
+///
+/// #[crossmist::main]
+/// fn main() {
+///     assert_eq!(example.run(5, 7).unwrap(), 12);
+/// }
+/// ```
+///
+/// `main` may be `async`, in which case you have to indicate which runtime to use. The runtime is
+/// only started after crossmist is initialized, so child processes don't start runtimes they don't
+/// need:
+///
+/// ```standalone_crate
+/// #[crossmist::func]
+/// fn example(a: i32, b: i32) -> i32 {
+///     a + b
+/// }
+///
+/// #[crossmist::main(tokio(flavor = "current_thread"))]
+/// async fn main() {
+///     assert_eq!(example.run_tokio(5, 7).await.unwrap(), 12);
+/// }
+/// ```
+///
+/// ```ignore
+/// #[crossmist::main(tokio)]
+/// async fn main() {}
+///
+/// #[crossmist::main(smol)]
+/// async fn main() {}
+/// ```
+///
+/// Operands to `tokio(...)` are forwarded to `tokio::main`. Combining this attribute with
+/// `#[tokio::main]` or similar attributes is an error, and so is applying it twice:
+///
+/// ```compile_fail
+/// #[crossmist::main]
+/// #[crossmist::main]
+/// fn main() {}
+/// ```
+///
+/// To register hooks, call [`Init`] manually instead.
+pub use crossmist_derive::main;
+
+/// Mark a test that uses crossmist.
+///
+/// Tests using crossmist can't call [`init`], since the test harness generates `main`. This
+/// attribute works as a replacement for `#[test]` that initializes crossmist instead, so crossmist
+/// can be used in unit and integration tests with the default harness:
+///
+/// ```ignore
+/// #[crossmist::func]
+/// fn example(a: i32, b: i32) -> i32 {
+///     a + b
+/// }
+///
+/// #[crossmist::test]
+/// fn test_example() {
+///     assert_eq!(example.run(5, 7).unwrap(), 12);
+/// }
+///
+/// #[crossmist::test(tokio)]
+/// async fn test_example_tokio() {
+///     assert_eq!(example.run_tokio(5, 7).await.unwrap(), 12);
+/// }
+///
+/// #[crossmist::test(smol)]
+/// async fn test_example_smol() {
+///     assert_eq!(example.run_smol(5, 7).await.unwrap(), 12);
+/// }
+/// ```
+///
+/// Operands to `tokio(...)` are forwarded to `tokio::main`. By default, the current-thread runtime
+/// is used, just like with `#[tokio::test]`. Other attributes, like `#[should_panic]` and
+/// `#[ignore]`, can be used as usual.
+///
+/// Child processes are started by re-running the test binary with a filter that selects a
+/// `#[crossmist::test]` function, which passes control to crossmist before running the test body.
+/// Don't call [`init`] in test binaries that use this attribute.
+pub use crossmist_derive::test;
+
+/// Generate a client and a server for calling the methods of a trait in another process.
+///
+/// For a trait `Name`, this attribute generates:
+///
+/// - `NameCall`, an enum with a variant per method, holding its arguments,
+/// - `NameResponse`, an enum with a variant per method, holding its return value,
+/// - `NameClient`, with a method per trait method that sends a call over a [`Duplex`] and waits for
+///   the response,
+/// - `NameServer`, which receives calls and dispatches them to an implementation of the trait.
+///
+/// ```standalone_crate
+/// #[crossmist::service]
+/// trait Counter {
+///     fn add(&mut self, delta: i32) -> i32;
+///     fn get(&self) -> i32;
+/// }
+///
+/// struct LocalCounter(i32);
+///
+/// impl Counter for LocalCounter {
+///     fn add(&mut self, delta: i32) -> i32 {
+///         self.0 += delta;
+///         self.0
+///     }
+///     fn get(&self) -> i32 {
+///         self.0
+///     }
+/// }
+///
+/// #[crossmist::func]
+/// fn serve_counter(server: CounterServer) {
+///     server.serve(&mut LocalCounter(0)).unwrap();
+/// }
+///
+/// fn main() {
+///     crossmist::init();
+///     let (mut counter, server) = CounterClient::channel().unwrap();
+///     let child = serve_counter.spawn(server).unwrap();
+///     assert_eq!(counter.add(5).unwrap(), 5);
+///     assert_eq!(counter.add(2).unwrap(), 7);
+///     assert_eq!(counter.get().unwrap(), 7);
+///     drop(counter);
+///     child.join().unwrap();
+/// }
+/// ```
+///
+/// Methods must take `&self` or `&mut self`, and their arguments and return values must be
+/// [`Object`]s. Client methods return [`std::io::Result`] to report errors of the channel.
+///
+/// Methods can't be named `new`, `into_inner`, or `channel`, since the generated client has methods
+/// with these names:
+///
+/// ```compile_fail
+/// #[crossmist::service]
+/// trait Worker {
+///     fn channel(&self, request: String) -> String;
+/// }
+/// ```
+///
+/// Clients and servers wrap a channel of type `Chan`, which is [`Duplex`] by default. To use the
+/// service asynchronously, create them with `NameClient::new` and `NameServer::new` from a
+/// [`tokio::Duplex`] or a [`smol::Duplex`]. Their methods become `async` in this case. The trait
+/// may also have `async` methods, which are awaited by asynchronous servers; such traits can't be
+/// served by a synchronous server:
+///
+/// ```ignore
+/// let (client, server) = crossmist::tokio::duplex()?;
+/// let mut counter = CounterClient::new(client);
+/// let child = serve_counter_tokio.spawn_tokio(CounterServer::new(server)).await?;
+/// assert_eq!(counter.add(5).await?, 5);
+/// ```
+///
+/// Calls are handled one by one, in order.
+pub use crossmist_derive::service;
+
 /// Make a structure or a enum serializable.
 ///
 /// This derive macro enables the corresponding type to be passed via channels and to and from child
@@ -417,11 +618,14 @@ pub use crossmist_derive::Object;
 
 #[doc(hidden)]
 pub mod imp;
-pub use imp::init;
+pub use imp::{Init, init, set_in_process};
 
 pub mod serde;
 pub use serde::*;
 
+pub mod options;
+pub use options::SpawnOptions;
+
 mod owning_ref;
 
 mod platform {
@@ -430,12 +634,14 @@ mod platform {
     pub mod unix {
         pub(crate) mod entry;
         pub(crate) mod internals;
+        pub(crate) mod setup;
         pub(crate) mod subprocess;
     }
     #[cfg(windows)]
     pub mod windows {
         pub(crate) mod entry;
         pub(crate) mod internals;
+        pub(crate) mod setup;
         pub(crate) mod subprocess;
     }
 }
@@ -454,15 +660,35 @@ pub mod tokio;
 
 #[doc(inline)]
 pub use asynchronous::KillHandle;
-pub use blocking::{Child, Duplex, Receiver, Sender, channel, duplex};
+pub use blocking::{Child, Duplex, Receiver, Sender, TryRecv, TrySend, channel, duplex};
 
 pub(crate) mod relocation;
 
+mod trace;
+
 mod builtins;
 mod unsized_builtins;
 
 pub mod fns;
 pub use fns::*;
 
+pub mod broadcast;
+pub use broadcast::broadcast;
+
+pub mod mpsc;
+
+pub mod multiplex;
+
+pub mod replay;
+
+pub mod select;
+pub use select::Select;
+
+#[cfg(feature = "tracing")]
+pub mod tracing;
+
+#[cfg(feature = "log")]
+mod log_forwarding;
+
 pub mod static_ref;
 pub use static_ref::StaticRef;
@@ -0,0 +1,115 @@
+//! Forwarding log records from children to the parent's logger.
+//!
+//! When the `log` feature is enabled and the parent has a logger, each child gets a channel to a
+//! thread in the parent that re-emits the records it receives via the parent's logger. The target
+//! of each record is prefixed with the name of the function and the process ID of the child, e.g.
+//! `my_crate::worker[1234]::my_crate::db`.
+
+use crate::{Object, Receiver, Sender};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::io::Result;
+use std::sync::Mutex;
+
+#[derive(Object)]
+struct ForwardedRecord {
+    level: usize,
+    target: String,
+    message: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+}
+
+/// The child's end of the forwarding channel.
+#[derive(Object)]
+pub(crate) struct LogForwarding {
+    sender: Sender<ForwardedRecord>,
+    max_level: usize,
+}
+
+/// Start forwarding records from a child, unless logging is disabled in this process.
+///
+/// The returned receiver is closed when the forwarder finishes, so that the parent can wait for it
+/// without blocking an asynchronous runtime.
+pub(crate) fn start(
+    function: &'static str,
+    pid: u32,
+) -> Result<Option<(LogForwarding, Receiver<()>)>> {
+    let max_level = log::max_level();
+    if max_level == LevelFilter::Off {
+        return Ok(None);
+    }
+
+    let (sender, receiver) = crate::channel()?;
+    let (done_tx, done_rx) = crate::channel::<()>()?;
+    std::thread::Builder::new()
+        .name("crossmist-log".to_string())
+        .spawn(move || {
+            forward(receiver, format!("{function}[{pid}]"));
+            drop(done_tx);
+        })?;
+    Ok(Some((
+        LogForwarding {
+            sender,
+            max_level: max_level as usize,
+        },
+        done_rx,
+    )))
+}
+
+// Re-emit records until the child closes the channel, i.e. exits.
+fn forward(mut receiver: Receiver<ForwardedRecord>, prefix: String) {
+    while let Ok(Some(record)) = receiver.recv() {
+        let Some(level) = Level::iter().find(|level| *level as usize == record.level) else {
+            continue;
+        };
+        let target = format!("{prefix}::{}", record.target);
+        log::logger().log(
+            &Record::builder()
+                .level(level)
+                .target(&target)
+                .args(format_args!("{}", record.message))
+                .module_path(record.module_path.as_deref())
+                .file(record.file.as_deref())
+                .line(record.line)
+                .build(),
+        );
+    }
+}
+
+struct ForwardingLogger(Mutex<Sender<ForwardedRecord>>);
+
+impl Log for ForwardingLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        // The parent's logger applies its own filters when the record is re-emitted.
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let record = ForwardedRecord {
+            level: record.level() as usize,
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+            module_path: record.module_path().map(str::to_string),
+            file: record.file().map(str::to_string),
+            line: record.line(),
+        };
+        // There's nowhere to report the failure to if the parent is gone.
+        if let Ok(mut sender) = self.0.lock() {
+            let _ = sender.send(record);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the forwarding logger in a child. Does nothing if a logger has already been set.
+pub(crate) fn install(forwarding: LogForwarding) {
+    let logger = ForwardingLogger(Mutex::new(forwarding.sender));
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        let max_level = LevelFilter::iter()
+            .find(|level| *level as usize == forwarding.max_level)
+            .unwrap_or(LevelFilter::Trace);
+        log::set_max_level(max_level);
+    }
+}
@@ -0,0 +1,150 @@
+//! Multi-producer, single-consumer channels.
+//!
+//! A [`Sender`] created by [`channel`] can be cloned and passed to any number of processes, all of
+//! which send messages to the same [`Receiver`]. This is handy for collecting results from a pool
+//! of children:
+//!
+//! ```standalone_crate
+//! use crossmist::mpsc;
+//!
+//! #[crossmist::func]
+//! fn worker(id: u32, mut results: mpsc::Sender<(u32, String)>) {
+//!     results.send((id, format!("hello from worker {id}"))).unwrap();
+//! }
+//!
+//! fn main() {
+//!     crossmist::init();
+//!
+//!     let (results_tx, mut results_rx) = mpsc::channel::<(u32, String)>().unwrap();
+//!     let children: Vec<_> = (0..4)
+//!         .map(|id| worker.spawn(id, results_tx.clone()).unwrap())
+//!         .collect();
+//!     drop(results_tx);
+//!
+//!     let mut results = Vec::new();
+//!     while let Some((id, _)) = results_rx.recv().unwrap() {
+//!         results.push(id);
+//!     }
+//!     results.sort();
+//!     assert_eq!(results, [0, 1, 2, 3]);
+//!
+//!     for child in children {
+//!         child.join().unwrap();
+//!     }
+//! }
+//! ```
+//!
+//! The receiver gets `None` once all senders are dropped.
+//!
+//! Large messages are split into several packets. To prevent packets of messages sent
+//! concurrently from interleaving, the senders share a lock, which is held while a message is
+//! being sent. If a process is killed while sending a message, the lock is never released and
+//! [`send`](Sender::send) blocks forever in the other senders, so prefer to terminate children
+//! gracefully. [`send_timeout`](Sender::send_timeout) fails instead if the lock cannot be acquired
+//! in time.
+
+use crate::{Object, Receiver};
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+
+// A lock shared between processes: whoever receives the token holds the lock.
+#[derive(Debug, Object)]
+struct Lock {
+    release: crate::Sender<()>,
+    acquire: Receiver<()>,
+}
+
+impl Lock {
+    fn new() -> Result<Self> {
+        let (mut release, acquire) = crate::channel()?;
+        release.send(())?;
+        Ok(Self { release, acquire })
+    }
+
+    fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            release: self.release.try_clone()?,
+            acquire: self.acquire.try_clone()?,
+        })
+    }
+}
+
+/// The transmitting side of a multi-producer channel.
+///
+/// Unlike [`crate::Sender`], this type can be cloned. All clones send messages to the same
+/// [`Receiver`].
+#[derive(Debug, Object)]
+pub struct Sender<T: Object> {
+    inner: crate::Sender<T>,
+    lock: Lock,
+}
+
+/// Create a multi-producer, single-consumer channel.
+pub fn channel<T: Object>() -> Result<(Sender<T>, Receiver<T>)> {
+    let (inner, rx) = crate::channel()?;
+    Ok((
+        Sender {
+            inner,
+            lock: Lock::new()?,
+        },
+        rx,
+    ))
+}
+
+impl<T: Object> Sender<T> {
+    /// Send a value to the receiver.
+    ///
+    /// Blocks while another sender is sending a message.
+    pub fn send(&mut self, value: T) -> Result<()> {
+        // We hold a copy of the releasing side, so the channel can't be closed.
+        self.lock.acquire.recv()?;
+        let result = self.inner.send(value);
+        self.lock.release.send(())?;
+        result
+    }
+
+    /// Send a value to the receiver, waiting at most `timeout` for other senders to finish sending
+    /// their messages.
+    ///
+    /// If the timeout expires, an error of kind [`TimedOut`](ErrorKind::TimedOut) is returned and
+    /// the value is dropped without being sent. This usually means that a sender was killed while
+    /// sending a message, in which case the channel cannot be used anymore. The timeout does not
+    /// apply to sending the message itself, which blocks until the receiver has room for it.
+    pub fn send_timeout(&mut self, value: T, timeout: Duration) -> Result<()> {
+        match self.lock.acquire.recv_timeout(timeout) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "Timed out waiting for other senders to finish sending",
+                ));
+            }
+            Err(e) => return Err(e),
+        }
+        let result = self.inner.send(value);
+        self.lock.release.send(())?;
+        result
+    }
+
+    /// Create another sender to the same receiver.
+    ///
+    /// This duplicates file descriptors (or handles, on Windows), which may fail.
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            inner: self.inner.try_clone()?,
+            lock: self.lock.try_clone()?,
+        })
+    }
+}
+
+impl<T: Object> Clone for Sender<T> {
+    /// Create another sender to the same receiver.
+    ///
+    /// # Panics
+    ///
+    /// Panics if file descriptors (or handles, on Windows) cannot be duplicated. Use
+    /// [`try_clone`](Self::try_clone) to handle this error.
+    fn clone(&self) -> Self {
+        self.try_clone().expect("Failed to clone mpsc::Sender")
+    }
+}
@@ -0,0 +1,258 @@
+//! Pipelined requests over a single channel.
+//!
+//! [`Duplex::request`](asynchronous::Duplex::request) takes `&mut self`, so only one request can be
+//! in flight at a time. [`Multiplexed`] wraps an asynchronous duplex and tags each request with an
+//! ID, so that many tasks can send requests and await responses concurrently. The other side
+//! receives `(id, request)` pairs and sends `(id, response)` pairs back, in any order:
+//!
+//! ```ignore
+//! #[crossmist::func(tokio)]
+//! async fn server(mut chan: crossmist::tokio::Duplex<(u64, i32), (u64, i32)>) {
+//!     while let Some((id, x)) = chan.recv().await.unwrap() {
+//!         chan.send((id, x * 2)).await.unwrap();
+//!     }
+//! }
+//!
+//! let (ours, theirs) = crossmist::tokio::duplex()?;
+//! let child = server.spawn_tokio(theirs).await?;
+//! let client = crossmist::tokio::Multiplexed::new(ours)?;
+//! let tasks: Vec<_> = (0..10)
+//!     .map(|x| {
+//!         let client = client.clone();
+//!         tokio::spawn(async move { client.request(x).await })
+//!     })
+//!     .collect();
+//! ```
+//!
+//! There is no background task: whichever task is waiting for a response reads the channel and
+//! hands out the responses to other tasks, so the client works with any runtime.
+
+use crate::{
+    Object,
+    asynchronous::{self, AsyncStream, Receiver, Sender},
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::poll_fn;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Poll, Waker};
+
+/// A client that sends requests tagged with IDs and routes responses to the tasks awaiting them.
+///
+/// Cloning the client is cheap; all clones share the channel.
+pub struct Multiplexed<Stream: AsyncStream, S: Object, R: Object> {
+    shared: Arc<Mutex<State<Stream, S, R>>>,
+}
+
+struct State<Stream: AsyncStream, S: Object, R: Object> {
+    sender: Sender<Stream, (u64, S)>,
+    receiver: Receiver<Stream, (u64, R)>,
+    // The request whose message `sender` is sending, if any. The message is finished by whichever
+    // task sends next, even if this request has been cancelled.
+    sending: Option<u64>,
+    send_waiters: Vec<Waker>,
+    next_id: u64,
+    pending: HashMap<u64, Slot<R>>,
+    // Set when the channel can no longer be used.
+    error: Option<(ErrorKind, String)>,
+}
+
+enum Slot<R> {
+    Waiting(Option<Waker>),
+    Ready(R),
+}
+
+impl<Stream: AsyncStream, S: Object, R: Object> State<Stream, S, R> {
+    fn check(&self) -> Result<()> {
+        match self.error {
+            Some((kind, ref message)) => Err(Error::new(kind, message.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn fail(&mut self, kind: ErrorKind, message: String) {
+        self.error.get_or_insert((kind, message));
+        self.wake_all();
+    }
+
+    // The stream only wakes the task that polled it last. Every task that stops polling it wakes
+    // the others, so that one of them takes over.
+    fn wake_all(&mut self) {
+        for waker in self.send_waiters.drain(..) {
+            waker.wake();
+        }
+        for slot in self.pending.values_mut() {
+            if let Slot::Waiting(waker) = slot
+                && let Some(waker) = waker.take()
+            {
+                waker.wake();
+            }
+        }
+    }
+}
+
+// Forgets the request if the task is cancelled, so that the response is dropped on arrival.
+struct Request<'a, Stream: AsyncStream, S: Object, R: Object> {
+    shared: &'a Mutex<State<Stream, S, R>>,
+    id: u64,
+}
+
+impl<Stream: AsyncStream, S: Object, R: Object> Drop for Request<'_, Stream, S, R> {
+    fn drop(&mut self) {
+        let mut state = lock(self.shared);
+        state.pending.remove(&self.id);
+        state.wake_all();
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().expect("Multiplexed state is poisoned")
+}
+
+impl<Stream: AsyncStream, S: Object, R: Object> Multiplexed<Stream, S, R> {
+    /// Wrap a channel.
+    ///
+    /// This duplicates the underlying file descriptor (or socket, on Windows), so that requests
+    /// can be sent while another task is receiving a response.
+    pub fn new(chan: asynchronous::Duplex<Stream, (u64, S), (u64, R)>) -> Result<Self> {
+        let stream = asynchronous::try_clone_stream(&chan.fd)?;
+        let receiver = unsafe { Receiver::from_stream(stream) };
+        Ok(Self {
+            shared: Arc::new(Mutex::new(State {
+                sender: chan.into_sender(),
+                receiver,
+                sending: None,
+                send_waiters: Vec::new(),
+                next_id: 0,
+                pending: HashMap::new(),
+                error: None,
+            })),
+        })
+    }
+
+    /// Send a request and wait for the response to it.
+    ///
+    /// Other tasks may send requests and receive responses concurrently. If the other side closes
+    /// the channel before responding, an error is returned.
+    ///
+    /// This method is cancel-safe: dropping the future only forgets the request, and the response
+    /// is dropped when it arrives. If the request has been partially sent by then, the rest of it
+    /// is sent before the next request.
+    pub async fn request(&self, value: S) -> Result<R> {
+        let id = {
+            let mut state = lock(&self.shared);
+            state.check()?;
+            let id = state.next_id;
+            state.next_id += 1;
+            state.pending.insert(id, Slot::Waiting(None));
+            id
+        };
+        let _request = Request {
+            shared: &self.shared,
+            id,
+        };
+
+        let mut value = Some(value);
+        poll_fn(|cx| -> Poll<Result<()>> {
+            let mut state = lock(&self.shared);
+            state.check()?;
+            loop {
+                if value.is_none() && state.sending != Some(id) {
+                    return Poll::Ready(Ok(()));
+                }
+                match state.sender.poll_flush(cx) {
+                    Poll::Ready(Ok(())) => {
+                        state.sending = None;
+                        for waker in state.send_waiters.drain(..) {
+                            waker.wake();
+                        }
+                    }
+                    Poll::Ready(Err(e)) => {
+                        state.fail(e.kind(), e.to_string());
+                        return Poll::Ready(Err(e));
+                    }
+                    Poll::Pending => {
+                        if !state.send_waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                            state.send_waiters.push(cx.waker().clone());
+                        }
+                        return Poll::Pending;
+                    }
+                }
+                if let Some(value) = value.take() {
+                    if let Err(e) = state.sender.begin_send((id, value)) {
+                        state.fail(e.kind(), e.to_string());
+                        return Poll::Ready(Err(e));
+                    }
+                    state.sending = Some(id);
+                }
+            }
+        })
+        .await?;
+
+        // Read messages until the response to our request arrives, handing out the others.
+        poll_fn(|cx| -> Poll<Result<R>> {
+            let mut state = lock(&self.shared);
+            if matches!(state.pending.get(&id), Some(Slot::Ready(_)))
+                && let Some(Slot::Ready(response)) = state.pending.remove(&id)
+            {
+                return Poll::Ready(Ok(response));
+            }
+            state.check()?;
+            loop {
+                match state.receiver.poll_recv(cx) {
+                    Poll::Ready(Ok(Some((response_id, response)))) if response_id == id => {
+                        state.pending.remove(&id);
+                        // Let another waiting task take over reading.
+                        state.wake_all();
+                        return Poll::Ready(Ok(response));
+                    }
+                    Poll::Ready(Ok(Some((response_id, response)))) => {
+                        // Responses to cancelled requests are dropped.
+                        if let Some(slot) = state.pending.get_mut(&response_id)
+                            && let Slot::Waiting(Some(waker)) =
+                                std::mem::replace(slot, Slot::Ready(response))
+                        {
+                            waker.wake();
+                        }
+                    }
+                    Poll::Ready(Ok(None)) => {
+                        let message =
+                            "The other side closed the channel before responding to the request";
+                        state.fail(ErrorKind::UnexpectedEof, message.to_string());
+                        return Poll::Ready(Err(Error::new(ErrorKind::UnexpectedEof, message)));
+                    }
+                    Poll::Ready(Err(e)) => {
+                        state.fail(e.kind(), e.to_string());
+                        return Poll::Ready(Err(e));
+                    }
+                    Poll::Pending => {
+                        state
+                            .pending
+                            .insert(id, Slot::Waiting(Some(cx.waker().clone())));
+                        return Poll::Pending;
+                    }
+                }
+            }
+        })
+        .await
+    }
+}
+
+impl<Stream: AsyncStream, S: Object, R: Object> Clone for Multiplexed<Stream, S, R> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<Stream: AsyncStream, S: Object, R: Object> fmt::Debug for Multiplexed<Stream, S, R> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let state = lock(&self.shared);
+        fmt.debug_struct("Multiplexed")
+            .field("pending", &state.pending.len())
+            .field("error", &state.error)
+            .finish()
+    }
+}
@@ -0,0 +1,522 @@
+//! Configuring child processes.
+//!
+//! By default, a child process runs with the same privileges and resources as its parent.
+//! [`SpawnOptions`] lets you restrict the child before it starts executing user code. Options are
+//! passed to the `spawn_with`, `run_with`, `spawn_tokio_with`, and similar methods generated by
+//! `#[crossmist::func]`:
+//!
+//! ```ignore
+//! let options = SpawnOptions::new();
+//! let child = my_process.spawn_with(&options, arg1, arg2)?;
+//! ```
+//!
+//! The plain `spawn` and `run` methods are equivalent to passing [`SpawnOptions::new()`].
+//!
+//! Options are applied by the child after it has received its entrypoint and arguments, but before
+//! the arguments are deserialized and the function is invoked. If applying an option fails, the
+//! child exits without running the function, and `spawn_with` returns the error.
+
+use crate::Object;
+#[cfg(unix)]
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind};
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+
+/// Options for starting a child process.
+///
+/// See the [module-level documentation](self) for more information.
+#[derive(Clone, Debug, Default, Object)]
+pub struct SpawnOptions {
+    #[cfg(target_os = "linux")]
+    pub(crate) seccomp: Option<SeccompFilter>,
+    #[cfg(target_os = "linux")]
+    pub(crate) landlock: Option<LandlockRuleset>,
+    #[cfg(unix)]
+    pub(crate) uid: Option<u32>,
+    #[cfg(unix)]
+    pub(crate) gid: Option<u32>,
+    #[cfg(unix)]
+    pub(crate) groups: Option<Vec<u32>>,
+    #[cfg(target_os = "linux")]
+    pub(crate) clear_capabilities: bool,
+    #[cfg(target_os = "linux")]
+    pub(crate) no_new_privs: bool,
+    #[cfg(target_os = "linux")]
+    pub(crate) cpu_affinity: Option<Vec<usize>>,
+    #[cfg(target_os = "linux")]
+    pub(crate) scheduler: Option<(i32, i32)>,
+    #[cfg(unix)]
+    pub(crate) nice: Option<i32>,
+    #[cfg(target_os = "linux")]
+    pub(crate) oom_score_adj: Option<i32>,
+    #[cfg(unix)]
+    pub(crate) inherit_fds: bool,
+    #[cfg(unix)]
+    pub(crate) wrapper: Option<Vec<OsString>>,
+    pub(crate) wait_for_debugger: bool,
+}
+
+impl SpawnOptions {
+    /// Create default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Install a seccomp filter in the child.
+    ///
+    /// Only syscalls allowed by the filter can be invoked by the child once the filter is
+    /// installed. This also sets the `no_new_privs` attribute of the child, as required by the
+    /// kernel for unprivileged processes.
+    ///
+    /// ```standalone_crate
+    /// use crossmist::{SpawnOptions, options::SeccompFilter};
+    ///
+    /// #[crossmist::func]
+    /// fn add(a: i32, b: i32) -> i32 {
+    ///     a + b
+    /// }
+    ///
+    /// fn main() {
+    ///     crossmist::init();
+    ///     let options = SpawnOptions::new().seccomp(SeccompFilter::compute_only());
+    ///     assert_eq!(add.run_with(&options, 5, 7).unwrap(), 12);
+    /// }
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn seccomp(mut self, filter: SeccompFilter) -> Self {
+        self.seccomp = Some(filter);
+        self
+    }
+
+    /// Restrict filesystem access of the child with Landlock.
+    ///
+    /// Only paths listed in the ruleset can be opened by the child once it is applied. File
+    /// descriptors that the child already has or receives later, e.g. [`std::fs::File`] objects
+    /// passed via arguments or channels, keep working regardless of their paths.
+    ///
+    /// ```standalone_crate
+    /// use crossmist::{SpawnOptions, options::LandlockRuleset};
+    /// use std::{fs::File, io::Read};
+    ///
+    /// #[crossmist::func]
+    /// fn read(mut file: File) -> String {
+    ///     let mut contents = String::new();
+    ///     file.read_to_string(&mut contents).unwrap();
+    ///     contents
+    /// }
+    ///
+    /// fn main() {
+    ///     crossmist::init();
+    ///     let options = SpawnOptions::new().landlock(LandlockRuleset::new());
+    ///     let file = File::open("/proc/self/cmdline").unwrap();
+    ///     assert!(!read.run_with(&options, file).unwrap().is_empty());
+    /// }
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn landlock(mut self, ruleset: LandlockRuleset) -> Self {
+        self.landlock = Some(ruleset);
+        self
+    }
+
+    /// Run the child as a different user.
+    ///
+    /// This requires the parent to be privileged, e.g. run as root. Unless
+    /// [`groups`](Self::groups) is also set, the supplementary groups of the child are cleared, so
+    /// that it doesn't keep the groups of the parent.
+    ///
+    /// On Linux, the child loses all capabilities when switching from root to a different user.
+    #[cfg(unix)]
+    pub fn uid(mut self, uid: u32) -> Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    /// Run the child with a different primary group.
+    ///
+    /// This requires the parent to be privileged, e.g. run as root.
+    #[cfg(unix)]
+    pub fn gid(mut self, gid: u32) -> Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Set the supplementary groups of the child.
+    ///
+    /// This requires the parent to be privileged, e.g. run as root.
+    #[cfg(unix)]
+    pub fn groups(mut self, groups: impl IntoIterator<Item = u32>) -> Self {
+        self.groups = Some(groups.into_iter().collect());
+        self
+    }
+
+    /// Clear all capability sets of the child, including the ambient and bounding sets.
+    ///
+    /// The bounding set can only be cleared if the parent has `CAP_SETPCAP`, and is left intact
+    /// otherwise. Use [`no_new_privs`](Self::no_new_privs) to prevent the child from regaining
+    /// capabilities by executing binaries regardless.
+    #[cfg(target_os = "linux")]
+    pub fn clear_capabilities(mut self) -> Self {
+        self.clear_capabilities = true;
+        self
+    }
+
+    /// Set the `no_new_privs` attribute of the child, so that it cannot gain privileges by
+    /// executing setuid binaries or binaries with file capabilities.
+    ///
+    /// ```standalone_crate
+    /// use crossmist::SpawnOptions;
+    ///
+    /// #[crossmist::func]
+    /// fn no_new_privs() -> i32 {
+    ///     unsafe { libc::prctl(libc::PR_GET_NO_NEW_PRIVS, 0, 0, 0, 0) }
+    /// }
+    ///
+    /// fn main() {
+    ///     crossmist::init();
+    ///     let options = SpawnOptions::new().no_new_privs();
+    ///     assert_eq!(no_new_privs.run_with(&options).unwrap(), 1);
+    /// }
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn no_new_privs(mut self) -> Self {
+        self.no_new_privs = true;
+        self
+    }
+
+    /// Restrict the child to the given CPUs.
+    ///
+    /// ```standalone_crate
+    /// use crossmist::SpawnOptions;
+    ///
+    /// #[crossmist::func]
+    /// fn current_cpu() -> i32 {
+    ///     unsafe { libc::sched_getcpu() }
+    /// }
+    ///
+    /// fn main() {
+    ///     crossmist::init();
+    ///
+    ///     // Pick one of the CPUs this process may run on.
+    ///     let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    ///     unsafe { libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut set) };
+    ///     let cpu = (0..libc::CPU_SETSIZE as usize)
+    ///         .find(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+    ///         .unwrap();
+    ///
+    ///     let options = SpawnOptions::new().cpu_affinity([cpu]);
+    ///     assert_eq!(current_cpu.run_with(&options).unwrap(), cpu as i32);
+    /// }
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn cpu_affinity(mut self, cpus: impl IntoIterator<Item = usize>) -> Self {
+        self.cpu_affinity = Some(cpus.into_iter().collect());
+        self
+    }
+
+    /// Set the scheduling policy of the child.
+    ///
+    /// Real-time policies typically require the parent to be privileged.
+    #[cfg(target_os = "linux")]
+    pub fn scheduler(mut self, policy: SchedPolicy) -> Self {
+        self.scheduler = Some(match policy {
+            SchedPolicy::Other => (libc::SCHED_OTHER, 0),
+            SchedPolicy::Batch => (libc::SCHED_BATCH, 0),
+            SchedPolicy::Idle => (libc::SCHED_IDLE, 0),
+            SchedPolicy::Fifo(priority) => (libc::SCHED_FIFO, priority),
+            SchedPolicy::RoundRobin(priority) => (libc::SCHED_RR, priority),
+        });
+        self
+    }
+
+    /// Set the nice value of the child.
+    ///
+    /// Nice values range from -20 (highest priority) to 19 (lowest priority). Decreasing the value
+    /// below that of the parent typically requires the parent to be privileged.
+    #[cfg(unix)]
+    pub fn nice(mut self, nice: i32) -> Self {
+        self.nice = Some(nice);
+        self
+    }
+
+    /// Adjust how likely the child is to be killed by the OOM killer.
+    ///
+    /// The adjustment ranges from -1000 (never kill) to 1000 (kill first). Decreasing the value
+    /// below that of the parent requires the parent to have `CAP_SYS_RESOURCE`.
+    #[cfg(target_os = "linux")]
+    pub fn oom_score_adj(mut self, adj: i32) -> Self {
+        self.oom_score_adj = Some(adj);
+        self
+    }
+
+    /// Let the child inherit file descriptors that the parent has leaked.
+    ///
+    /// By default, the child closes all file descriptors except for standard streams and its
+    /// channel to the parent immediately after starting, in case some file descriptors were opened
+    /// without `O_CLOEXEC`, e.g. by C libraries. File descriptors passed explicitly via arguments or
+    /// channels are not affected by this. This option disables the cleanup, which is useful if the
+    /// child is supposed to find inherited file descriptors by their numbers.
+    #[cfg(unix)]
+    pub fn inherit_fds(mut self) -> Self {
+        self.inherit_fds = true;
+        self
+    }
+
+    /// Start the child under a wrapper command, e.g. `strace -f`, `valgrind`, `taskset -c 0`, or
+    /// `perf record`.
+    ///
+    /// The wrapper is looked up in `PATH` and executed with the path to the current executable and
+    /// the usual arguments appended. The channel to the parent is inherited through the wrapper, so
+    /// the wrapper must not close unknown file descriptors. An empty command disables wrapping.
+    ///
+    /// If this option is not set, the environment variable `CROSSMIST_WRAPPER` is used instead, split
+    /// on whitespace, so that e.g. `CROSSMIST_WRAPPER="strace -f"` traces all children without
+    /// recompiling. The wrapper is ignored in [in-process mode](crate::set_in_process).
+    #[cfg(unix)]
+    pub fn wrapper<S: Into<OsString>>(mut self, command: impl IntoIterator<Item = S>) -> Self {
+        self.wrapper = Some(command.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Make the child wait for a debugger to attach before running the function.
+    ///
+    /// The child prints its process ID and the name of the function to stderr and blocks until a
+    /// debugger attaches. On Unix-like systems, sending `SIGCONT` to the child resumes it too.
+    ///
+    /// Setting the environment variable `CROSSMIST_DEBUG_WAIT` to a comma-separated list of
+    /// function names or paths, e.g. `worker_fn` or `my_crate::workers::worker_fn`, has the same
+    /// effect for matching functions, and `*` matches all functions. This option is ignored in
+    /// [in-process mode](crate::set_in_process).
+    pub fn wait_for_debugger(mut self) -> Self {
+        self.wait_for_debugger = true;
+        self
+    }
+
+    /// Whether the child needs to apply any options and report the result to the parent.
+    pub(crate) fn needs_child_setup(&self) -> bool {
+        #[cfg(unix)]
+        if self.uid.is_some() || self.gid.is_some() || self.groups.is_some() || self.nice.is_some()
+        {
+            return true;
+        }
+        #[cfg(target_os = "linux")]
+        if self.seccomp.is_some()
+            || self.landlock.is_some()
+            || self.clear_capabilities
+            || self.no_new_privs
+            || self.cpu_affinity.is_some()
+            || self.scheduler.is_some()
+            || self.oom_score_adj.is_some()
+        {
+            return true;
+        }
+        false
+    }
+}
+
+/// A syscall allow-list enforced via seccomp.
+///
+/// A filter always allows the syscalls crossmist itself needs to communicate with the parent,
+/// allocate memory for messages, and exit, so channels keep working regardless of the contents of
+/// the allow-list. Syscalls are identified by their numbers for the current architecture, e.g.
+/// [`libc::SYS_getpid`].
+///
+/// Note that asynchronous runtimes typically need additional syscalls (e.g. `epoll_wait`,
+/// `eventfd2`, and `clone3` for tokio), and so do many seemingly pure operations, like printing to
+/// standard output or spawning threads.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Debug, Object)]
+pub struct SeccompFilter {
+    pub(crate) syscalls: Vec<libc::c_long>,
+    pub(crate) violation_action: u32,
+}
+
+#[cfg(target_os = "linux")]
+impl SeccompFilter {
+    /// Create a filter that only allows syscalls used by crossmist channels, memory allocation, and
+    /// process exit.
+    ///
+    /// Violations kill the process.
+    pub fn new() -> Self {
+        Self {
+            syscalls: vec![
+                libc::SYS_read,
+                libc::SYS_write,
+                libc::SYS_recvmsg,
+                libc::SYS_sendmsg,
+                libc::SYS_close,
+                // Debug builds of std use `fcntl` to check that adopted file descriptors are open.
+                libc::SYS_fcntl,
+                #[cfg(any(target_arch = "x86", target_arch = "arm"))]
+                libc::SYS_fcntl64,
+                libc::SYS_exit,
+                libc::SYS_exit_group,
+                // Messages are (de)serialized into heap buffers, so the allocator must work.
+                libc::SYS_brk,
+                libc::SYS_mmap,
+                #[cfg(any(target_arch = "x86", target_arch = "arm"))]
+                libc::SYS_mmap2,
+                libc::SYS_munmap,
+                libc::SYS_mremap,
+                libc::SYS_mprotect,
+                libc::SYS_madvise,
+                // std removes the alternate signal stack on exit.
+                libc::SYS_sigaltstack,
+            ],
+            violation_action: libc::SECCOMP_RET_KILL_PROCESS,
+        }
+    }
+
+    /// Create a filter suitable for synchronous functions that only perform computations and
+    /// communicate via crossmist channels.
+    ///
+    /// In addition to syscalls allowed by [`SeccompFilter::new`], this allows futexes, time
+    /// queries, random number generation, signal masking, and aborting on panic.
+    pub fn compute_only() -> Self {
+        Self::new().allow_all([
+            libc::SYS_readv,
+            libc::SYS_writev,
+            libc::SYS_futex,
+            libc::SYS_sched_yield,
+            libc::SYS_clock_gettime,
+            libc::SYS_getrandom,
+            libc::SYS_rt_sigprocmask,
+            libc::SYS_rt_sigreturn,
+            libc::SYS_getpid,
+            libc::SYS_gettid,
+            libc::SYS_tgkill,
+        ])
+    }
+
+    /// Allow a syscall.
+    pub fn allow(mut self, syscall: libc::c_long) -> Self {
+        self.syscalls.push(syscall);
+        self
+    }
+
+    /// Allow several syscalls.
+    pub fn allow_all(mut self, syscalls: impl IntoIterator<Item = libc::c_long>) -> Self {
+        self.syscalls.extend(syscalls);
+        self
+    }
+
+    /// Configure what happens when the child invokes a syscall that is not allowed.
+    pub fn on_violation(mut self, action: SeccompAction) -> Self {
+        self.violation_action = match action {
+            SeccompAction::KillProcess => libc::SECCOMP_RET_KILL_PROCESS,
+            SeccompAction::Errno(errno) => {
+                libc::SECCOMP_RET_ERRNO | (errno as u32 & libc::SECCOMP_RET_DATA)
+            }
+            SeccompAction::Log => libc::SECCOMP_RET_LOG,
+        };
+        self
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Default for SeccompFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The action taken when a seccomp filter is violated.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeccompAction {
+    /// Kill the whole process with `SIGSYS`.
+    KillProcess,
+    /// Fail the syscall with the given error number.
+    Errno(i32),
+    /// Allow the syscall, but log it to the kernel audit log. Useful for building allow-lists.
+    Log,
+}
+
+/// A Linux scheduling policy.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// The default time-sharing policy, `SCHED_OTHER`.
+    Other,
+    /// `SCHED_BATCH`, for CPU-intensive non-interactive work.
+    Batch,
+    /// `SCHED_IDLE`, for work that should only run when the system is otherwise idle.
+    Idle,
+    /// The real-time first-in first-out policy `SCHED_FIFO` with the given priority (1 to 99).
+    Fifo(i32),
+    /// The real-time round-robin policy `SCHED_RR` with the given priority (1 to 99).
+    RoundRobin(i32),
+}
+
+/// A set of filesystem paths the child is allowed to access, enforced via Landlock.
+///
+/// Access to a directory includes access to everything beneath it. Paths that don't exist cause
+/// spawning to fail.
+///
+/// Landlock is only available on Linux 5.13 and later, and may be disabled in the kernel
+/// configuration. On such systems, the ruleset is silently ignored unless
+/// [`LandlockRuleset::require`] is used. Newer kernels restrict more operations, e.g. renaming
+/// files across directories is only restricted since Linux 5.19.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Debug, Default, Object)]
+pub struct LandlockRuleset {
+    pub(crate) read_only: Vec<PathBuf>,
+    pub(crate) read_write: Vec<PathBuf>,
+    pub(crate) required: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl LandlockRuleset {
+    /// Create a ruleset that denies access to all paths.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow reading and executing files under a path.
+    pub fn read_only(mut self, path: impl Into<PathBuf>) -> Self {
+        self.read_only.push(path.into());
+        self
+    }
+
+    /// Allow reading, writing, creating, and removing files under a path.
+    pub fn read_write(mut self, path: impl Into<PathBuf>) -> Self {
+        self.read_write.push(path.into());
+        self
+    }
+
+    /// Fail to spawn the child if the kernel does not support Landlock, instead of running it
+    /// without restrictions.
+    pub fn require(mut self) -> Self {
+        self.required = true;
+        self
+    }
+}
+
+/// An error that occured while applying options in the child, transferred to the parent.
+#[derive(Debug, Object)]
+pub(crate) struct SetupError {
+    errno: Option<i32>,
+    message: String,
+}
+
+impl From<Error> for SetupError {
+    fn from(err: Error) -> Self {
+        Self {
+            errno: err.raw_os_error(),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<SetupError> for Error {
+    fn from(err: SetupError) -> Self {
+        let kind = match err.errno {
+            Some(errno) => Error::from_raw_os_error(errno).kind(),
+            None => ErrorKind::Other,
+        };
+        Error::new(
+            kind,
+            format!("Failed to set up the subprocess: {}", err.message),
+        )
+    }
+}
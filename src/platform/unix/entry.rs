@@ -1,8 +1,8 @@
 use crate::asynchronous::handle_entry;
 use rustix::io::{FdFlags, fcntl_setfd};
-use std::os::unix::io::{FromRawFd, OwnedFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 
-pub(crate) fn crossmist_main(mut args: std::env::Args) -> ! {
+pub(crate) fn crossmist_main(mut args: impl Iterator<Item = String>) -> ! {
     let fd = unsafe {
         OwnedFd::from_raw_fd(
             args.next()
@@ -12,5 +12,63 @@ pub(crate) fn crossmist_main(mut args: std::env::Args) -> ! {
         )
     };
     fcntl_setfd(&fd, FdFlags::CLOEXEC).expect("Failed to set O_CLOEXEC for the file descriptor");
+    for flag in args {
+        if let Some(stdout) = flag.strip_prefix("--stdout=") {
+            let stdout =
+                unsafe { OwnedFd::from_raw_fd(stdout.parse().expect("Failed to parse stdout fd")) };
+            let mut fd1 = unsafe { OwnedFd::from_raw_fd(1) };
+            rustix::io::dup2(&stdout, &mut fd1).expect("Failed to restore stdout");
+            core::mem::forget(fd1);
+            continue;
+        }
+        match flag.as_str() {
+            "--close-fds" => close_fds_except(fd.as_raw_fd()),
+            _ => {
+                // Most likely the parent and the child are different builds of the program, so
+                // there is nothing sensible to do.
+                eprintln!("crossmist: unknown flag {flag}");
+                std::process::exit(1);
+            }
+        }
+    }
     handle_entry(fd);
 }
+
+// Close all file descriptors other than stdio and the channel, in case the parent has leaked some.
+// This has to be done before anything is received from the channel.
+fn close_fds_except(keep: RawFd) {
+    let ranges = [(3, keep - 1), (keep + 1, RawFd::MAX)];
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut closed = true;
+        for (first, last) in ranges {
+            if first <= last {
+                closed &=
+                    unsafe { libc::syscall(libc::SYS_close_range, first as u32, last as u32, 0) }
+                        == 0;
+            }
+        }
+        if closed {
+            return;
+        }
+        // `close_range` is only supported since Linux 5.9, fall back to enumerating fds.
+    }
+
+    let fds: Vec<RawFd> = std::fs::read_dir("/dev/fd")
+        .expect("Failed to list file descriptors")
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    // This includes the fd `read_dir` used, but it has already been closed, so closing it again is
+    // harmless.
+    for fd in fds {
+        if ranges
+            .iter()
+            .any(|&(first, last)| (first..=last).contains(&fd))
+        {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+}
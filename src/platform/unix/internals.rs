@@ -1,19 +1,22 @@
-use crate::{Deserializer, Object, Serializer};
+use crate::{Deserializer, Object, Serializer, trace};
 use rustix::{
     cmsg_space,
+    event::{PollFd, PollFlags, Timespec, poll},
+    io::Errno,
     net::{
         self, AddressFamily, RecvAncillaryBuffer, RecvAncillaryMessage, RecvFlags,
         SendAncillaryBuffer, SendAncillaryMessage, SendFlags, SocketFlags, SocketType, recvmsg,
         sendmsg,
     },
 };
-use std::io::{Error, IoSlice, IoSliceMut, Result};
+use std::io::{Error, ErrorKind, IoSlice, IoSliceMut, Result};
 use std::marker::PhantomData;
 use std::mem::MaybeUninit;
 use std::os::unix::{
     io::{BorrowedFd, OwnedFd},
     net::UnixStream,
 };
+use std::time::Instant;
 
 pub(crate) const MAX_PACKET_SIZE: usize = 16 * 1024;
 pub(crate) const MAX_PACKET_FDS: usize = 253; // SCM_MAX_FD
@@ -29,21 +32,71 @@ pub(crate) fn socketpair() -> Result<(UnixStream, UnixStream)> {
     Ok((tx.into(), rx.into()))
 }
 
-pub(crate) struct SingleObjectSender<'a> {
-    socket_fd: BorrowedFd<'a>,
+/// Wait until the socket is readable or writable, or fail with `TimedOut` at the deadline.
+pub(crate) fn wait_ready(socket_fd: BorrowedFd<'_>, write: bool, deadline: Instant) -> Result<()> {
+    let flags = if write { PollFlags::OUT } else { PollFlags::IN };
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let mut fds = [PollFd::new(&socket_fd, flags)];
+        // Durations that don't fit in `Timespec` are as good as infinite.
+        match poll(&mut fds, Timespec::try_from(timeout).ok().as_ref()) {
+            Ok(0) => {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "Channel operation timed out",
+                ));
+            }
+            Ok(_) => return Ok(()),
+            Err(Errno::INTR) => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Wait until one of the sockets is readable and return its index, or `None` at the deadline.
+pub(crate) fn wait_any_readable(
+    sockets: &[BorrowedFd<'_>],
+    deadline: Option<Instant>,
+) -> Result<Option<usize>> {
+    let mut fds: Vec<PollFd> = sockets
+        .iter()
+        .map(|fd| PollFd::new(fd, PollFlags::IN))
+        .collect();
+    loop {
+        let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        // Durations that don't fit in `Timespec` are as good as infinite.
+        let timeout = timeout.and_then(|timeout| Timespec::try_from(timeout).ok());
+        match poll(&mut fds, timeout.as_ref()) {
+            Ok(0) => return Ok(None),
+            Ok(_) => {
+                if let Some(index) = fds.iter().position(|fd| !fd.revents().is_empty()) {
+                    return Ok(Some(index));
+                }
+            }
+            Err(Errno::INTR) => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+pub(crate) struct SingleObjectSender {
     fds: Vec<OwnedFd>,
     buffer: Vec<u8>,
     data_pos: usize,
     fds_pos: usize,
     flags: SendFlags,
+    nonblocking_start: bool,
+    // Only called when tracing, since the name is not free to compute.
+    type_name: fn() -> &'static str,
+    packets: usize,
 }
 
-impl<'a> SingleObjectSender<'a> {
-    pub(crate) fn new<T: Object>(socket_fd: BorrowedFd<'a>, value: T, blocking: bool) -> Self {
+impl SingleObjectSender {
+    pub(crate) fn new<T: Object>(value: T, blocking: bool) -> Result<Self> {
         let mut s = Serializer::new();
         s.serialize(value);
-        Self {
-            socket_fd,
+        s.check()?;
+        Ok(Self {
             fds: s.fds,
             buffer: s.data,
             data_pos: 0,
@@ -53,10 +106,44 @@ impl<'a> SingleObjectSender<'a> {
             } else {
                 SendFlags::DONTWAIT
             },
-        }
+            nonblocking_start: false,
+            type_name: trace::type_name::<T>,
+            packets: 0,
+        })
+    }
+
+    /// Fail with `WouldBlock` instead of blocking if the first packet cannot be sent immediately.
+    /// Once the first packet is sent, the rest of the message is sent as usual.
+    pub(crate) fn start_nonblocking(&mut self) {
+        self.nonblocking_start = true;
     }
 
-    pub(crate) fn send_next(&mut self) -> Result<()> {
+    /// Switch between blocking and non-blocking I/O for the rest of the message.
+    pub(crate) fn set_blocking(&mut self, blocking: bool) {
+        self.flags.set(SendFlags::DONTWAIT, !blocking);
+    }
+
+    /// Whether no part of the message has been sent yet.
+    pub(crate) fn is_unstarted(&self) -> bool {
+        self.packets == 0
+    }
+
+    /// Recover the value that was supposed to be sent.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the type of the value the sender was created with, and no part of the message
+    /// may have been sent.
+    pub(crate) unsafe fn into_value<T: Object>(self) -> T {
+        let mut d = Deserializer::from(Serializer {
+            data: self.buffer,
+            fds: self.fds,
+            error: None,
+        });
+        unsafe { d.deserialize() }
+    }
+
+    pub(crate) fn send_next(&mut self, socket_fd: BorrowedFd<'_>) -> Result<()> {
         let mut space = [MaybeUninit::uninit(); cmsg_space!(ScmRights(MAX_PACKET_FDS))];
         let mut cmsg_buffer = SendAncillaryBuffer::new(&mut space);
 
@@ -72,41 +159,56 @@ impl<'a> SingleObjectSender<'a> {
             assert!(cmsg_buffer.push(SendAncillaryMessage::ScmRights(fds)));
 
             let n_written = sendmsg(
-                self.socket_fd,
+                socket_fd,
                 &[
                     IoSlice::new(&[is_last as u8]),
                     IoSlice::new(&self.buffer[self.data_pos..buffer_end]),
                 ],
                 &mut cmsg_buffer,
-                self.flags,
+                if self.nonblocking_start && self.packets == 0 {
+                    self.flags | SendFlags::DONTWAIT
+                } else {
+                    self.flags
+                },
             )?;
 
             self.data_pos += n_written - 1;
             self.fds_pos = fds_end;
+            self.packets += 1;
 
             if is_last {
+                if trace::is_enabled() {
+                    trace::message(
+                        &trace::channel_id(socket_fd),
+                        trace::Direction::Send,
+                        (self.type_name)(),
+                        &self.buffer,
+                        self.fds.len(),
+                        Some(self.packets),
+                    );
+                }
                 return Ok(());
             }
         }
     }
 }
 
-pub(crate) struct SingleObjectReceiver<'a, T: Object> {
-    socket_fd: BorrowedFd<'a>,
+pub(crate) struct SingleObjectReceiver<T: Object> {
     buffer: Vec<u8>,
     data_pos: usize,
     fds: Vec<OwnedFd>,
     flags: RecvFlags,
+    nonblocking_start: bool,
+    packets: usize,
     terminated: bool,
     marker: PhantomData<fn() -> T>,
 }
 
-unsafe impl<T: Object> Send for SingleObjectReceiver<'_, T> {}
+unsafe impl<T: Object> Send for SingleObjectReceiver<T> {}
 
-impl<'a, T: Object> SingleObjectReceiver<'a, T> {
-    pub(crate) unsafe fn new(socket_fd: BorrowedFd<'a>, blocking: bool) -> Self {
+impl<T: Object> SingleObjectReceiver<T> {
+    pub(crate) unsafe fn new(blocking: bool) -> Self {
         Self {
-            socket_fd,
             buffer: Vec::new(),
             data_pos: 0,
             fds: Vec::new(),
@@ -115,12 +217,30 @@ impl<'a, T: Object> SingleObjectReceiver<'a, T> {
             } else {
                 RecvFlags::DONTWAIT
             },
+            nonblocking_start: false,
+            packets: 0,
             terminated: false,
             marker: PhantomData,
         }
     }
 
-    pub(crate) fn recv_next(&mut self) -> Result<Option<T>> {
+    /// Fail with `WouldBlock` instead of blocking if no message is available. Once the first
+    /// packet is received, the rest of the message is received as usual.
+    pub(crate) fn start_nonblocking(&mut self) {
+        self.nonblocking_start = true;
+    }
+
+    /// Switch between blocking and non-blocking I/O for the rest of the message.
+    pub(crate) fn set_blocking(&mut self, blocking: bool) {
+        self.flags.set(RecvFlags::DONTWAIT, !blocking);
+    }
+
+    /// Whether no part of the message has been received yet.
+    pub(crate) fn is_unstarted(&self) -> bool {
+        self.packets == 0
+    }
+
+    pub(crate) fn recv_next(&mut self, socket_fd: BorrowedFd<'_>) -> Result<Option<Serializer>> {
         assert!(
             !self.terminated,
             "Calling recv_next after it returned Ok(Some(...)) or Err(...) is undefined behavior",
@@ -139,10 +259,14 @@ impl<'a, T: Object> SingleObjectReceiver<'a, T> {
             ];
 
             let message = recvmsg(
-                self.socket_fd,
+                socket_fd,
                 &mut iovecs,
                 &mut cmsg_buffer,
-                self.flags | RecvFlags::CMSG_CLOEXEC,
+                if self.nonblocking_start && self.packets == 0 {
+                    self.flags | RecvFlags::DONTWAIT | RecvFlags::CMSG_CLOEXEC
+                } else {
+                    self.flags | RecvFlags::CMSG_CLOEXEC
+                },
             )?;
 
             for cmsg in cmsg_buffer.drain() {
@@ -154,6 +278,9 @@ impl<'a, T: Object> SingleObjectReceiver<'a, T> {
 
             if message.bytes == 0 {
                 if self.data_pos == 0 && self.fds.is_empty() {
+                    if trace::is_enabled() {
+                        trace::closed(&trace::channel_id(socket_fd), trace::type_name::<T>());
+                    }
                     return Ok(None);
                 } else {
                     return Err(Error::other("Unterminated data on stream"));
@@ -165,6 +292,7 @@ impl<'a, T: Object> SingleObjectReceiver<'a, T> {
             }
 
             self.data_pos += message.bytes - 1;
+            self.packets += 1;
             if marker[0] != 1 {
                 continue;
             }
@@ -172,11 +300,21 @@ impl<'a, T: Object> SingleObjectReceiver<'a, T> {
             self.terminated = true;
 
             self.buffer.truncate(self.data_pos);
-            let mut d = Deserializer::from(Serializer {
+            if trace::is_enabled() {
+                trace::message(
+                    &trace::channel_id(socket_fd),
+                    trace::Direction::Recv,
+                    trace::type_name::<T>(),
+                    &self.buffer,
+                    self.fds.len(),
+                    Some(self.packets),
+                );
+            }
+            return Ok(Some(Serializer {
                 data: std::mem::take(&mut self.buffer),
                 fds: std::mem::take(&mut self.fds),
-            });
-            return Ok(Some(unsafe { d.deserialize() }));
+                error: None,
+            }));
         }
     }
 }
@@ -0,0 +1,451 @@
+use crate::SpawnOptions;
+use std::io::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(target_os = "linux")]
+use {
+    crate::options::{LandlockRuleset, SeccompFilter},
+    std::io::{Error, ErrorKind},
+    std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd},
+    std::path::Path,
+};
+
+// Applies options in the child before user code runs. The order matters: each step may prevent the
+// following steps from succeeding, e.g. seccomp filters may forbid syscalls used by other steps.
+pub(crate) fn apply(options: &SpawnOptions) -> Result<()> {
+    set_resources(options)?;
+    drop_privileges(options)?;
+    #[cfg(target_os = "linux")]
+    if let Some(ruleset) = &options.landlock {
+        restrict_landlock(ruleset)?;
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(filter) = &options.seccomp {
+        install_seccomp(filter)?;
+    }
+    Ok(())
+}
+
+// Raising priority requires privileges, so this has to be done before dropping them.
+fn set_resources(options: &SpawnOptions) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    if let Some(cpus) = &options.cpu_affinity {
+        let mut set: libc::cpu_set_t = unsafe { core::mem::zeroed() };
+        for &cpu in cpus {
+            if cpu >= libc::CPU_SETSIZE as usize {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("CPU {cpu} is out of range"),
+                ));
+            }
+            unsafe {
+                libc::CPU_SET(cpu, &mut set);
+            }
+        }
+        check(unsafe { libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set) })?;
+    }
+    #[cfg(target_os = "linux")]
+    if let Some((policy, priority)) = options.scheduler {
+        let param = libc::sched_param {
+            sched_priority: priority,
+        };
+        check(unsafe { libc::sched_setscheduler(0, policy, &param) })?;
+    }
+    if let Some(nice) = options.nice {
+        check(unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) })?;
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(adj) = options.oom_score_adj {
+        std::fs::write("/proc/self/oom_score_adj", adj.to_string())?;
+    }
+    Ok(())
+}
+
+fn drop_privileges(options: &SpawnOptions) -> Result<()> {
+    // Capabilities in the bounding set can only be dropped while we still have `CAP_SETPCAP`, which
+    // is lost on `setuid`.
+    #[cfg(target_os = "linux")]
+    if options.clear_capabilities {
+        clear_ambient_and_bounding_capabilities()?;
+    }
+
+    // Groups have to be changed before the user, which would otherwise lose the permission to do
+    // that.
+    let groups = match &options.groups {
+        Some(groups) => Some(&groups[..]),
+        None if options.uid.is_some() => Some(&[][..]),
+        None => None,
+    };
+    if let Some(groups) = groups {
+        check(unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) })?;
+    }
+    if let Some(gid) = options.gid {
+        check(unsafe { libc::setgid(gid) })?;
+    }
+    if let Some(uid) = options.uid {
+        check(unsafe { libc::setuid(uid) })?;
+    }
+
+    #[cfg(target_os = "linux")]
+    if options.clear_capabilities {
+        clear_capability_sets()?;
+    }
+    #[cfg(target_os = "linux")]
+    if options.no_new_privs {
+        check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn clear_ambient_and_bounding_capabilities() -> Result<()> {
+    let ret = unsafe {
+        libc::prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_CLEAR_ALL,
+            0,
+            0,
+            0,
+        )
+    };
+    if let Err(err) = check(ret) {
+        // Ambient capabilities are only supported since Linux 4.3.
+        if err.raw_os_error() != Some(libc::EINVAL) {
+            return Err(err);
+        }
+    }
+
+    // Iterate until the kernel reports an unknown capability.
+    for cap in 0.. {
+        match unsafe { libc::prctl(libc::PR_CAPBSET_READ, cap, 0, 0, 0) } {
+            -1 => break,
+            0 => continue,
+            _ => {}
+        }
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) } == -1 {
+            let err = Error::last_os_error();
+            if err.raw_os_error() == Some(libc::EPERM) {
+                // We lack `CAP_SETPCAP`, so the bounding set can't be changed.
+                break;
+            }
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn clear_capability_sets() -> Result<()> {
+    // Definitions from `linux/capability.h`, which `libc` doesn't export.
+    #[repr(C)]
+    struct CapUserHeader {
+        version: u32,
+        pid: libc::c_int,
+    }
+    #[repr(C)]
+    struct CapUserData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+    const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+    let header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [const {
+        CapUserData {
+            effective: 0,
+            permitted: 0,
+            inheritable: 0,
+        }
+    }; 2];
+    check(unsafe { libc::syscall(libc::SYS_capset, &raw const header, data.as_ptr()) })
+}
+
+fn check<T: PartialEq + From<i8>>(ret: T) -> Result<()> {
+    if ret == T::from(-1) {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// `AUDIT_ARCH_*` constants from `linux/audit.h`, which `libc` doesn't export.
+#[cfg(target_os = "linux")]
+const AUDIT_ARCH: Option<u32> = if cfg!(target_arch = "x86_64") {
+    Some(0xc000003e)
+} else if cfg!(target_arch = "x86") {
+    Some(0x40000003)
+} else if cfg!(target_arch = "aarch64") {
+    Some(0xc00000b7)
+} else if cfg!(target_arch = "arm") {
+    Some(0x40000028)
+} else if cfg!(target_arch = "riscv64") {
+    Some(0xc00000f3)
+} else if cfg!(target_arch = "loongarch64") {
+    Some(0xc0000102)
+} else if cfg!(all(target_arch = "powerpc64", target_endian = "little")) {
+    Some(0xc0000015)
+} else if cfg!(all(target_arch = "powerpc64", target_endian = "big")) {
+    Some(0x80000015)
+} else if cfg!(target_arch = "s390x") {
+    Some(0x80000016)
+} else {
+    None
+};
+
+#[cfg(target_os = "linux")]
+fn compile_seccomp(filter: &SeccompFilter, arch: u32) -> Vec<libc::sock_filter> {
+    let stmt = |code: u32, k: u32| libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let jump = |code: u32, k: u32, jt: u8, jf: u8| libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    };
+
+    let nr_offset = core::mem::offset_of!(libc::seccomp_data, nr) as u32;
+    let arch_offset = core::mem::offset_of!(libc::seccomp_data, arch) as u32;
+
+    let mut program = vec![
+        // Syscall numbers are architecture-specific, so kill processes that switch the ABI.
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, arch_offset),
+        jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, arch, 1, 0),
+        stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, nr_offset),
+    ];
+    if cfg!(target_arch = "x86_64") {
+        // x32 syscalls share the architecture with x86-64, but have `__X32_SYSCALL_BIT` set.
+        program.push(jump(
+            libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+            0x40000000,
+            0,
+            1,
+        ));
+        program.push(stmt(
+            libc::BPF_RET | libc::BPF_K,
+            libc::SECCOMP_RET_KILL_PROCESS,
+        ));
+    }
+    for &syscall in &filter.syscalls {
+        program.push(jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            syscall as u32,
+            0,
+            1,
+        ));
+        program.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+    }
+    program.push(stmt(libc::BPF_RET | libc::BPF_K, filter.violation_action));
+    program
+}
+
+#[cfg(target_os = "linux")]
+fn install_seccomp(filter: &SeccompFilter) -> Result<()> {
+    let Some(arch) = AUDIT_ARCH else {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "seccomp filters are not supported on this architecture",
+        ));
+    };
+
+    let mut program = compile_seccomp(filter, arch);
+    let Ok(len) = u16::try_from(program.len()) else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "seccomp allow-list is too long",
+        ));
+    };
+    let prog = libc::sock_fprog {
+        len,
+        filter: program.as_mut_ptr(),
+    };
+
+    check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+    // Synchronize threads in case any have already been started.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_TSYNC,
+            &raw const prog,
+        )
+    };
+    check(ret)?;
+    if ret != 0 {
+        // With TSYNC, a positive return value is the ID of the thread that couldn't be synced.
+        return Err(Error::other(format!(
+            "Failed to install seccomp filter on thread {ret}"
+        )));
+    }
+    Ok(())
+}
+
+// Landlock definitions from `linux/landlock.h`, which `libc` doesn't export.
+#[cfg(target_os = "linux")]
+mod landlock {
+    pub const CREATE_RULESET_VERSION: u32 = 1 << 0;
+    pub const RULE_PATH_BENEATH: u32 = 1;
+
+    pub const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    pub const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    pub const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    pub const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    pub const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+    pub const ACCESS_FS_IOCTL_DEV: u64 = 1 << 15;
+
+    // Rights that make sense for regular files, as opposed to directories.
+    pub const ACCESS_FILE: u64 = ACCESS_FS_EXECUTE
+        | ACCESS_FS_WRITE_FILE
+        | ACCESS_FS_READ_FILE
+        | ACCESS_FS_TRUNCATE
+        | ACCESS_FS_IOCTL_DEV;
+    pub const ACCESS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+    // Filesystem rights supported by each ABI version.
+    pub fn access_fs_for_abi(abi: i64) -> u64 {
+        match abi {
+            ..=0 => 0,
+            1 => (1 << 13) - 1,
+            2 => (1 << 14) - 1,
+            3 | 4 => (1 << 15) - 1,
+            5.. => (1 << 16) - 1,
+        }
+    }
+
+    #[repr(C)]
+    pub struct RulesetAttr {
+        pub handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    pub struct PathBeneathAttr {
+        pub allowed_access: u64,
+        pub parent_fd: i32,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn add_landlock_rule(ruleset_fd: &OwnedFd, path: &Path, access: u64) -> Result<()> {
+    let path_fd = rustix::fs::open(
+        path,
+        rustix::fs::OFlags::PATH | rustix::fs::OFlags::CLOEXEC,
+        rustix::fs::Mode::empty(),
+    )
+    .map_err(|err| {
+        Error::new(
+            Error::from(err).kind(),
+            format!("Failed to open {}: {err}", path.display()),
+        )
+    })?;
+    let is_dir = rustix::fs::fstat(&path_fd)?.st_mode & libc::S_IFMT == libc::S_IFDIR;
+    let attr = landlock::PathBeneathAttr {
+        allowed_access: if is_dir {
+            access
+        } else {
+            access & landlock::ACCESS_FILE
+        },
+        parent_fd: path_fd.as_raw_fd(),
+    };
+    check(unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset_fd.as_raw_fd(),
+            landlock::RULE_PATH_BENEATH,
+            &raw const attr,
+            0,
+        )
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn restrict_landlock(ruleset: &LandlockRuleset) -> Result<()> {
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            core::ptr::null::<landlock::RulesetAttr>(),
+            0,
+            landlock::CREATE_RULESET_VERSION,
+        )
+    };
+    if abi == -1 {
+        let err = Error::last_os_error();
+        let unsupported = matches!(err.raw_os_error(), Some(libc::ENOSYS | libc::EOPNOTSUPP));
+        return if unsupported && !ruleset.required {
+            Ok(())
+        } else {
+            Err(err)
+        };
+    }
+
+    // Only handle rights known to the kernel, so that rulesets work on older kernels too.
+    let handled = landlock::access_fs_for_abi(abi);
+    let attr = landlock::RulesetAttr {
+        handled_access_fs: handled,
+    };
+    let ruleset_fd = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &raw const attr,
+            core::mem::size_of::<landlock::RulesetAttr>(),
+            0,
+        )
+    };
+    check(ruleset_fd)?;
+    let ruleset_fd = unsafe { OwnedFd::from_raw_fd(ruleset_fd as i32) };
+
+    for path in &ruleset.read_only {
+        add_landlock_rule(&ruleset_fd, path, landlock::ACCESS_READ & handled)?;
+    }
+    for path in &ruleset.read_write {
+        add_landlock_rule(&ruleset_fd, path, handled)?;
+    }
+
+    check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+    check(unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset_fd.as_raw_fd(), 0) })
+}
+
+// Block until a debugger attaches or `SIGCONT` is received.
+pub(crate) fn wait_for_debugger() {
+    static CONTINUED: AtomicBool = AtomicBool::new(false);
+    extern "C" fn on_sigcont(_: libc::c_int) {
+        CONTINUED.store(true, Ordering::Relaxed);
+    }
+
+    let mut action: libc::sigaction = unsafe { core::mem::zeroed() };
+    action.sa_sigaction = on_sigcont as *const () as libc::sighandler_t;
+    let mut old_action: libc::sigaction = unsafe { core::mem::zeroed() };
+    unsafe {
+        libc::sigaction(libc::SIGCONT, &action, &mut old_action);
+    }
+
+    while !CONTINUED.load(Ordering::Relaxed) && !is_traced() {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    unsafe {
+        libc::sigaction(libc::SIGCONT, &old_action, core::ptr::null_mut());
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_traced() -> bool {
+    let Ok(status) = std::fs::read_to_string("/proc/self/status") else {
+        return false;
+    };
+    status
+        .lines()
+        .filter_map(|line| line.strip_prefix("TracerPid:"))
+        .any(|pid| pid.trim() != "0")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_traced() -> bool {
+    false
+}
@@ -1,9 +1,13 @@
+use crate::SpawnOptions;
 use core::mem::MaybeUninit;
 use libc::c_char;
 use rustix::process::Pid;
-use std::ffi::CString;
-use std::io::{Error, Result};
-use std::os::unix::io::{AsRawFd, BorrowedFd};
+use std::ffi::{CStr, CString, OsStr};
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::{
+    ffi::OsStrExt,
+    io::{AsFd, AsRawFd, BorrowedFd},
+};
 
 // `libc` doesn't export `environ` because POSIX says it's not part of any header:
 // https://github.com/rust-lang/libc/pull/5339#discussion_r3677981017
@@ -44,17 +48,11 @@ impl Drop for FileActions {
     }
 }
 
-pub(crate) unsafe fn _spawn_child(child_fd: BorrowedFd<'_>) -> Result<Pid> {
+pub(crate) unsafe fn _spawn_child(child_fd: BorrowedFd<'_>, options: &SpawnOptions) -> Result<Pid> {
     let mut pid = 0;
 
-    let child_fd_str = CString::new(child_fd.as_raw_fd().to_string()).unwrap();
-    let argv = [
-        c"_crossmist_".as_ptr(),
-        child_fd_str.as_ptr(),
-        core::ptr::null(),
-    ];
-
     let mut file_actions = FileActions::new()?;
+    let mut args = vec![child_fd.as_raw_fd().to_string()];
 
     from_errno(unsafe {
         libc::posix_spawn_file_actions_adddup2(
@@ -64,15 +62,136 @@ pub(crate) unsafe fn _spawn_child(child_fd: BorrowedFd<'_>) -> Result<Pid> {
         )
     })?;
 
+    // Under the libtest harness, the child is the test binary, which prints a banner before
+    // running the test that passes control to us. Hide it by redirecting stdout until the child
+    // restores the original one.
+    let test_name = crate::imp::current_test_name();
+    let stdout;
+    if test_name.is_some() {
+        stdout = rustix::io::fcntl_dupfd_cloexec(std::io::stdout().as_fd(), 3)?;
+        from_errno(unsafe {
+            libc::posix_spawn_file_actions_adddup2(
+                file_actions.as_mut_ptr(),
+                stdout.as_raw_fd(),
+                stdout.as_raw_fd(),
+            )
+        })?;
+        from_errno(unsafe {
+            libc::posix_spawn_file_actions_addopen(
+                file_actions.as_mut_ptr(),
+                1,
+                c"/dev/null".as_ptr(),
+                libc::O_WRONLY,
+                0,
+            )
+        })?;
+        args.push(format!("--stdout={}", stdout.as_raw_fd()));
+    }
+
+    if !options.inherit_fds {
+        args.push("--close-fds".to_string());
+    }
+
+    let in_test = test_name.is_some();
+    let args = match test_name {
+        // Dispatch to any `#[crossmist::test]` function, which passes control to `init_test` before
+        // doing anything else.
+        Some(test_name) => vec![
+            "_crossmist_".to_string(),
+            format!("_crossmist_={}", args.join(",")),
+            "--exact".to_string(),
+            "--include-ignored".to_string(),
+            "--test-threads=1".to_string(),
+            "--nocapture".to_string(),
+            "-q".to_string(),
+            test_name.to_string(),
+        ],
+        None => core::iter::once("_crossmist_".to_string())
+            .chain(args)
+            .collect(),
+    };
+    let wrapper = match &options.wrapper {
+        Some(wrapper) => wrapper.clone(),
+        None => std::env::var_os("CROSSMIST_WRAPPER")
+            .map(|wrapper| {
+                wrapper
+                    .as_bytes()
+                    .split(u8::is_ascii_whitespace)
+                    .filter(|word| !word.is_empty())
+                    .map(|word| OsStr::from_bytes(word).to_owned())
+                    .collect()
+            })
+            .unwrap_or_default(),
+    };
+
+    let to_cstring = |arg: &[u8]| {
+        CString::new(arg)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "Argument contains a null byte"))
+    };
+    let mut argv = Vec::new();
+    for word in &wrapper {
+        argv.push(to_cstring(word.as_bytes())?);
+    }
+    let mut args = &args[..];
+    if !wrapper.is_empty() {
+        // The wrapper needs a real path to execute, after which the child expects `_crossmist_` to
+        // follow. In tests, the first argument identifies the child by itself.
+        argv.push(to_cstring(std::env::current_exe()?.as_os_str().as_bytes())?);
+        if in_test {
+            args = &args[1..];
+        }
+    }
+    for arg in args {
+        argv.push(to_cstring(arg.as_bytes())?);
+    }
+    let argv_ptrs: Vec<*const c_char> = argv
+        .iter()
+        .map(|arg| arg.as_ptr())
+        .chain([core::ptr::null()])
+        .collect();
+
+    // Tell a wrapped child to look for `_crossmist_` after the path to the executable, so that a
+    // program started by the user with `_crossmist_` as its first argument is not mistaken for a
+    // child. If we are a wrapped child ourselves, the variable is inherited, so it has to be
+    // removed for other children.
+    let mut envp = Vec::new();
+    let mut var = unsafe { environ };
+    while !unsafe { *var }.is_null() {
+        let entry = unsafe { *var };
+        if !unsafe { CStr::from_ptr(entry) }
+            .to_bytes()
+            .starts_with(b"CROSSMIST_WRAPPED=")
+        {
+            envp.push(entry);
+        }
+        var = unsafe { var.add(1) };
+    }
+    if !wrapper.is_empty() && !in_test {
+        envp.push(c"CROSSMIST_WRAPPED=1".as_ptr() as *mut c_char);
+    }
+    envp.push(core::ptr::null_mut());
+
     from_errno(unsafe {
-        libc::posix_spawn(
-            &raw mut pid,
-            c"/proc/self/exe".as_ptr(),
-            file_actions.as_ptr(),
-            core::ptr::null(),
-            &raw const argv as *const *mut c_char,
-            environ,
-        )
+        if wrapper.is_empty() {
+            libc::posix_spawn(
+                &raw mut pid,
+                c"/proc/self/exe".as_ptr(),
+                file_actions.as_ptr(),
+                core::ptr::null(),
+                argv_ptrs.as_ptr() as *const *mut c_char,
+                envp.as_ptr(),
+            )
+        } else {
+            // Look up the wrapper in `PATH`.
+            libc::posix_spawnp(
+                &raw mut pid,
+                argv[0].as_ptr(),
+                file_actions.as_ptr(),
+                core::ptr::null(),
+                argv_ptrs.as_ptr() as *const *mut c_char,
+                envp.as_ptr(),
+            )
+        }
     })?;
 
     Ok(Pid::from_raw(pid).unwrap())
@@ -2,7 +2,7 @@ use crate::{Sender, asynchronous::handle_entry, subprocess::load_init_handles};
 use std::os::windows::io::{AsRawSocket, FromRawSocket};
 use windows::Win32::Networking::WinSock;
 
-pub(crate) fn crossmist_main(_args: std::env::Args) -> ! {
+pub(crate) fn crossmist_main(_args: impl Iterator<Item = String>) -> ! {
     let mut data = WinSock::WSADATA::default();
     if unsafe { WinSock::WSAStartup(0x0202, &raw mut data) } != 0 {
         panic!(
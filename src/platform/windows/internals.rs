@@ -1,12 +1,14 @@
 use crate::{Deserializer, Object, Serializer, subprocess::HANDLE_BROKER};
 use std::io::{Error, ErrorKind, Result};
 use std::net::TcpStream;
+use std::os::windows::io::BorrowedSocket;
 use std::os::windows::io::{
     AsRawHandle, AsRawSocket, FromRawHandle, FromRawSocket, IntoRawHandle, OwnedHandle,
     OwnedSocket, RawHandle, RawSocket,
 };
 use std::path::PathBuf;
 use std::sync::LazyLock;
+use std::time::Instant;
 use windows::Win32::{
     Foundation::{self, HANDLE},
     Networking::WinSock,
@@ -157,13 +159,97 @@ pub(crate) fn try_socketpair() -> Result<Option<(TcpStream, TcpStream)>> {
     }
 }
 
-pub(crate) fn serialize_with_handles<T: Object>(value: T) -> Result<Vec<u8>> {
+/// Wait until the socket is readable or writable, or fail with `TimedOut` at the deadline.
+pub(crate) fn wait_ready(socket: BorrowedSocket<'_>, write: bool, deadline: Instant) -> Result<()> {
+    let mut fds = [WinSock::WSAPOLLFD {
+        fd: WinSock::SOCKET(socket.as_raw_socket() as usize),
+        events: if write {
+            WinSock::POLLWRNORM
+        } else {
+            WinSock::POLLRDNORM
+        },
+        revents: Default::default(),
+    }];
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        // Round up so that we don't wake up just before the deadline.
+        let timeout = timeout.as_micros().div_ceil(1000);
+        match unsafe {
+            WinSock::WSAPoll(
+                fds.as_mut_ptr(),
+                1,
+                i32::try_from(timeout).unwrap_or(i32::MAX),
+            )
+        } {
+            0 if Instant::now() < deadline => continue,
+            0 => {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "Channel operation timed out",
+                ));
+            }
+            WinSock::SOCKET_ERROR => return Err(Error::last_os_error()),
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Wait until one of the sockets is readable and return its index, or `None` at the deadline.
+pub(crate) fn wait_any_readable(
+    sockets: &[BorrowedSocket<'_>],
+    deadline: Option<Instant>,
+) -> Result<Option<usize>> {
+    let mut fds: Vec<WinSock::WSAPOLLFD> = sockets
+        .iter()
+        .map(|socket| WinSock::WSAPOLLFD {
+            fd: WinSock::SOCKET(socket.as_raw_socket() as usize),
+            events: WinSock::POLLRDNORM,
+            revents: Default::default(),
+        })
+        .collect();
+    loop {
+        let timeout = match deadline {
+            // Round up so that we don't wake up just before the deadline.
+            Some(deadline) => i32::try_from(
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .as_micros()
+                    .div_ceil(1000),
+            )
+            .unwrap_or(i32::MAX),
+            None => -1,
+        };
+        match unsafe { WinSock::WSAPoll(fds.as_mut_ptr(), fds.len() as u32, timeout) } {
+            0 if deadline.is_some_and(|deadline| Instant::now() >= deadline) => return Ok(None),
+            0 => continue,
+            WinSock::SOCKET_ERROR => return Err(Error::last_os_error()),
+            _ => {
+                if let Some(index) = fds.iter().position(|fd| fd.revents.0 != 0) {
+                    return Ok(Some(index));
+                }
+            }
+        }
+    }
+}
+
+/// Whether the socket is readable or writable right now.
+pub(crate) fn is_ready(socket: BorrowedSocket<'_>, write: bool) -> Result<bool> {
+    match wait_ready(socket, write, Instant::now()) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::TimedOut => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Returns the serialized data and the number of handles and sockets in it.
+pub(crate) fn serialize_with_handles<T: Object>(value: T) -> Result<(Vec<u8>, usize)> {
     let broker = HANDLE_BROKER
         .get()
         .expect("broker has not been initialized");
 
     let mut s = Serializer::new();
     s.serialize(value);
+    s.check()?;
 
     let copy_handle = |handle: RawHandle| -> Result<usize> {
         let mut remote_handle: HANDLE = Default::default();
@@ -198,14 +284,15 @@ pub(crate) fn serialize_with_handles<T: Object>(value: T) -> Result<Vec<u8>> {
         })
         .collect::<Result<Vec<usize>>>()?;
 
+    let count = remote_handles.len() + remote_sockets.len();
     let mut s1 = Serializer::new();
     s1.serialize(remote_handles);
     s1.serialize(remote_sockets);
     s1.write(&s.data);
-    Ok(s1.data)
+    Ok((s1.data, count))
 }
 
-pub(crate) unsafe fn deserialize_with_handles<T: Object>(serialized: Vec<u8>) -> Result<T> {
+pub(crate) unsafe fn deserialize_with_handles(serialized: Vec<u8>) -> Result<Serializer> {
     let broker = HANDLE_BROKER
         .get()
         .expect("broker has not been initialized");
@@ -214,6 +301,7 @@ pub(crate) unsafe fn deserialize_with_handles<T: Object>(serialized: Vec<u8>) ->
         data: serialized,
         handles: Vec::new(),
         sockets: Vec::new(),
+        error: None,
     });
 
     let steal_handle = |remote_handle: usize| -> Result<OwnedHandle> {
@@ -249,12 +337,10 @@ pub(crate) unsafe fn deserialize_with_handles<T: Object>(serialized: Vec<u8>) ->
         .collect::<Result<Vec<_>>>()?;
 
     let data = d.get_rest().to_vec();
-    Ok(unsafe {
-        Deserializer::from(Serializer {
-            data,
-            handles,
-            sockets,
-        })
-        .deserialize()
+    Ok(Serializer {
+        data,
+        handles,
+        sockets,
+        error: None,
     })
 }
@@ -0,0 +1,15 @@
+use crate::SpawnOptions;
+use std::io::Result;
+use windows::Win32::System::Diagnostics::Debug;
+
+// None of the options require setup on Windows yet.
+pub(crate) fn apply(_options: &SpawnOptions) -> Result<()> {
+    Ok(())
+}
+
+// Block until a debugger attaches.
+pub(crate) fn wait_for_debugger() {
+    while !unsafe { Debug::IsDebuggerPresent() }.as_bool() {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+}
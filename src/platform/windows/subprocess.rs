@@ -193,7 +193,16 @@ pub(crate) unsafe fn _spawn_child<'a>(child_socket: BorrowedSocket<'a>) -> Resul
 
         // Create the child in a (temporarily) kill-on-close job so that it doesn't remain in a coma
         // if we die before completing the startup.
-        let (process, thread, _, job) = spawn_suspended_in_job(Some(OsStr::new("_crossmist_")))?;
+        // Under the libtest harness, dispatch to any `#[crossmist::test]` function, which passes
+        // control to `init_test` before doing anything else.
+        let cmd_line = match crate::imp::current_test_name() {
+            Some(test_name) => format!(
+                "_crossmist_ _crossmist_ --exact --include-ignored --test-threads=1 --nocapture -q \
+                 {test_name}"
+            ),
+            None => "_crossmist_".to_string(),
+        };
+        let (process, thread, _, job) = spawn_suspended_in_job(Some(OsStr::new(&cmd_line)))?;
 
         let mut init_data = InitData {
             broker_pid: broker.pid,
@@ -0,0 +1,357 @@
+//! Recording and replaying channel traffic.
+//!
+//! When a child misbehaves after receiving a particular sequence of messages, it is often easier to
+//! debug it by feeding the same messages to the code again than by reproducing the whole system.
+//! [`Recorder`] wraps a [`Receiver`] or a [`Duplex`] and appends every received message to a
+//! capture file in its serialized form. [`Replay`] reads a capture file and produces the same
+//! messages again, without the original peer:
+//!
+//! ```standalone_crate
+//! use crossmist::replay::{Recorder, Replay};
+//!
+//! fn main() {
+//!     crossmist::init();
+//!
+//!     let (mut tx, rx) = crossmist::channel::<Vec<i32>>().unwrap();
+//!     let mut rx = Recorder::new(rx, Vec::new()).unwrap();
+//!     tx.send(vec![1, 2, 3]).unwrap();
+//!     drop(tx);
+//!     assert_eq!(rx.recv().unwrap(), Some(vec![1, 2, 3]));
+//!     assert_eq!(rx.recv().unwrap(), None);
+//!     let (_, capture) = rx.into_inner();
+//!
+//!     let mut replay = unsafe { Replay::<Vec<i32>, _>::new(&capture[..]) }.unwrap();
+//!     assert_eq!(replay.recv().unwrap(), Some(vec![1, 2, 3]));
+//!     assert_eq!(replay.recv().unwrap(), None);
+//! }
+//! ```
+//!
+//! Code that expects a [`Receiver`], e.g. a function running in a child process, can be fed from a
+//! capture with [`Replay::into_receiver`].
+//!
+//! The capture stores the serialized bytes of each message and the kinds of file descriptors (or
+//! handles, on Windows) attached to it. File descriptors themselves cannot be stored, so messages
+//! that contain them are recorded for inspection, but cannot be replayed.
+//!
+//! Just like channels, captures may only be replayed by the same executable file that recorded
+//! them.
+
+use crate::{Deserializer, Duplex, Object, Receiver, Serializer, asynchronous, blocking::block_on};
+use std::fmt;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::marker::PhantomData;
+
+const MAGIC: &[u8] = b"crossmist capture\n";
+
+const TAG_END: u8 = 0;
+const TAG_MESSAGE: u8 = 1;
+
+/// A channel that records all received messages to a capture.
+///
+/// `C` is either [`Receiver`] or [`Duplex`], `W` is the destination of the capture, usually a
+/// [`File`](std::fs::File). The capture is flushed after each message, so that it's complete even
+/// if the process crashes.
+#[derive(Debug)]
+pub struct Recorder<C, W: Write> {
+    channel: C,
+    capture: W,
+}
+
+/// Channels that can be recorded by [`Recorder`].
+///
+/// This trait is sealed and implemented for [`Receiver`] and [`Duplex`].
+pub trait Recordable: private::Sealed {}
+
+impl<T: Object> Recordable for Receiver<T> {}
+impl<S: Object, R: Object> Recordable for Duplex<S, R> {}
+
+mod private {
+    use crate::{Duplex, Object, Receiver};
+
+    pub trait Sealed {
+        fn message_type() -> &'static str;
+    }
+
+    impl<T: Object> Sealed for Receiver<T> {
+        fn message_type() -> &'static str {
+            std::any::type_name::<T>()
+        }
+    }
+
+    impl<S: Object, R: Object> Sealed for Duplex<S, R> {
+        fn message_type() -> &'static str {
+            std::any::type_name::<R>()
+        }
+    }
+}
+
+impl<C: Recordable, W: Write> Recorder<C, W> {
+    /// Start recording messages received from `channel` to `capture`.
+    ///
+    /// Only received messages are recorded. Messages sent via a [`Duplex`] are passed through as
+    /// is.
+    pub fn new(channel: C, mut capture: W) -> Result<Self> {
+        let type_name = C::message_type();
+        capture.write_all(MAGIC)?;
+        capture.write_all(&(type_name.len() as u64).to_le_bytes())?;
+        capture.write_all(type_name.as_bytes())?;
+        capture.flush()?;
+        Ok(Self { channel, capture })
+    }
+
+    fn record<T: Object>(&mut self, serialized: Option<Serializer>) -> Result<Option<T>> {
+        let Some(serialized) = serialized else {
+            self.capture.write_all(&[TAG_END])?;
+            self.capture.flush()?;
+            return Ok(None);
+        };
+
+        let kinds = fd_kinds(&serialized);
+        let mut record = vec![TAG_MESSAGE];
+        record.extend((serialized.data.len() as u64).to_le_bytes());
+        record.extend((kinds.len() as u64).to_le_bytes());
+        record.extend(kinds);
+        record.extend(&serialized.data);
+        self.capture.write_all(&record)?;
+        self.capture.flush()?;
+
+        Ok(Some(unsafe {
+            Deserializer::from(serialized).deserialize()
+        }))
+    }
+
+    /// Stop recording and return the channel and the capture.
+    pub fn into_inner(self) -> (C, W) {
+        (self.channel, self.capture)
+    }
+}
+
+impl<T: Object, W: Write> Recorder<Receiver<T>, W> {
+    /// Receive a value from the other side and record it.
+    ///
+    /// Returns `Ok(None)` if the other side has dropped the channel.
+    pub fn recv(&mut self) -> Result<Option<T>> {
+        let serialized = block_on(self.channel.0.recv_serialized(false))?;
+        self.record(serialized)
+    }
+}
+
+impl<S: Object, R: Object, W: Write> Recorder<Duplex<S, R>, W> {
+    /// Send a value to the other side.
+    pub fn send(&mut self, value: S) -> Result<()> {
+        self.channel.send(value)
+    }
+
+    /// Receive a value from the other side and record it.
+    ///
+    /// Returns `Ok(None)` if the other side has dropped the channel.
+    pub fn recv(&mut self) -> Result<Option<R>> {
+        let serialized = block_on(self.channel.0.recv_serialized(false))?;
+        self.record(serialized)
+    }
+
+    /// Send a value from the other side and wait for a response immediately, recording the
+    /// response.
+    ///
+    /// If the other side closes the channel before responding, an error is returned.
+    pub fn request(&mut self, value: S) -> Result<R> {
+        self.send(value)?;
+        self.recv()?.ok_or_else(|| {
+            Error::new(
+                ErrorKind::UnexpectedEof,
+                "The subprocess exitted before responding to the request",
+            )
+        })
+    }
+}
+
+#[cfg(unix)]
+fn fd_kinds(serialized: &Serializer) -> Vec<u8> {
+    use rustix::fs::{FileType, fstat};
+    serialized
+        .fds
+        .iter()
+        .map(
+            |fd| match fstat(fd).map(|stat| FileType::from_raw_mode(stat.st_mode)) {
+                Ok(FileType::RegularFile) => b'f',
+                Ok(FileType::Directory) => b'd',
+                Ok(FileType::Symlink) => b'l',
+                Ok(FileType::Fifo) => b'p',
+                Ok(FileType::Socket) => b's',
+                Ok(FileType::CharacterDevice) => b'c',
+                Ok(FileType::BlockDevice) => b'b',
+                _ => b'?',
+            },
+        )
+        .collect()
+}
+
+#[cfg(windows)]
+fn fd_kinds(serialized: &Serializer) -> Vec<u8> {
+    let handles = serialized.handles.iter().map(|_| b'h');
+    let sockets = serialized.sockets.iter().map(|_| b's');
+    handles.chain(sockets).collect()
+}
+
+/// A receiver that produces messages from a capture written by [`Recorder`].
+pub struct Replay<T: Object, Rd: Read> {
+    capture: Rd,
+    index: usize,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Object, Rd: Read> Replay<T, Rd> {
+    /// Start replaying `capture`.
+    ///
+    /// Returns an error if the capture was not recorded for messages of type `T`.
+    ///
+    /// # Safety
+    ///
+    /// The capture must have been recorded by the same executable file. Messages are deserialized
+    /// without validation, so replaying a corrupted or forged capture is undefined behavior.
+    pub unsafe fn new(mut capture: Rd) -> Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        capture.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "The file is not a crossmist capture",
+            ));
+        }
+
+        let len = read_u64(&mut capture)?;
+        let mut type_name = Vec::new();
+        (&mut capture).take(len).read_to_end(&mut type_name)?;
+        let expected = std::any::type_name::<T>();
+        if type_name != expected.as_bytes() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The capture contains messages of type {}, not {expected}",
+                    String::from_utf8_lossy(&type_name),
+                ),
+            ));
+        }
+
+        Ok(Self {
+            capture,
+            index: 0,
+            marker: PhantomData,
+        })
+    }
+
+    /// Produce the next recorded value.
+    ///
+    /// Returns `Ok(None)` if the other side dropped the channel at this point of the recording, or
+    /// if the recording ends here.
+    pub fn recv(&mut self) -> Result<Option<T>> {
+        let Some(data) = self.read_record()? else {
+            return Ok(None);
+        };
+        let mut s = Serializer::new();
+        s.data = data;
+        Ok(Some(unsafe { Deserializer::from(s).deserialize() }))
+    }
+
+    /// Produce the rest of the recorded values from a real channel.
+    ///
+    /// The receiver can be used in place of the original one, including by passing it to a child
+    /// process. All remaining messages are read from the capture right away, so that an error is
+    /// returned if any of them cannot be replayed. A background thread then sends them to the
+    /// receiver and closes the channel.
+    pub fn into_receiver(mut self) -> Result<Receiver<T>> {
+        let mut messages = Vec::new();
+        while let Some(data) = self.read_record()? {
+            messages.push(RawMessage(data));
+        }
+        let (mut tx, rx) = crate::channel::<RawMessage>()?;
+        std::thread::Builder::new()
+            .name("crossmist-replay".to_string())
+            .spawn(move || {
+                for message in messages {
+                    // The receiver may be dropped early.
+                    if tx.send(message).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        // Each message is received exactly as it was serialized by the recording side.
+        Ok(Receiver(unsafe {
+            asynchronous::Receiver::from_stream(rx.0.fd)
+        }))
+    }
+
+    // Read the serialized form of the next message.
+    fn read_record(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut tag = [0];
+        if self.capture.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+        match tag[0] {
+            TAG_END => return Ok(None),
+            TAG_MESSAGE => {}
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Unknown record in capture",
+                ));
+            }
+        }
+
+        let data_len = read_u64(&mut self.capture)?;
+        let fds_len = read_u64(&mut self.capture)?;
+        let mut kinds = Vec::new();
+        (&mut self.capture).take(fds_len).read_to_end(&mut kinds)?;
+        let mut data = Vec::new();
+        (&mut self.capture).take(data_len).read_to_end(&mut data)?;
+        if kinds.len() as u64 != fds_len || data.len() as u64 != data_len {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Truncated record in capture",
+            ));
+        }
+
+        let index = self.index;
+        self.index += 1;
+        if !kinds.is_empty() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "Message {index} in capture contains {} file descriptors ({}), which cannot be \
+                     replayed",
+                    kinds.len(),
+                    String::from_utf8_lossy(&kinds),
+                ),
+            ));
+        }
+
+        Ok(Some(data))
+    }
+}
+
+// A message serialized in advance, sent as is.
+struct RawMessage(Vec<u8>);
+
+unsafe impl Object for RawMessage {
+    fn serialize_self(self, s: &mut Serializer) {
+        s.write(&self.0);
+    }
+    unsafe fn deserialize_self(_d: &mut Deserializer) -> Self {
+        unreachable!()
+    }
+}
+
+impl<T: Object, Rd: Read + fmt::Debug> fmt::Debug for Replay<T, Rd> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Replay")
+            .field("capture", &self.capture)
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
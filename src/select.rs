@@ -0,0 +1,238 @@
+//! Waiting for several channels at once.
+//!
+//! [`Select`] waits until one of several blocking [`Receiver`]s or [`Duplex`]es has a message, a
+//! [`Child`] exits, or a timeout expires, and runs the handler registered for that event:
+//!
+//! ```standalone_crate
+//! use crossmist::Select;
+//! use std::time::Duration;
+//!
+//! #[derive(Debug, PartialEq)]
+//! enum Event {
+//!     Number(Option<i32>),
+//!     Text(Option<String>),
+//!     Timeout,
+//! }
+//!
+//! fn main() {
+//!     crossmist::init();
+//!
+//!     let (mut tx1, mut rx1) = crossmist::channel::<i32>().unwrap();
+//!     let (_tx2, mut rx2) = crossmist::channel::<String>().unwrap();
+//!     tx1.send(5).unwrap();
+//!
+//!     let event = Select::new()
+//!         .recv(&mut rx1, Event::Number)
+//!         .recv(&mut rx2, Event::Text)
+//!         .timeout(Duration::from_secs(1), || Event::Timeout)
+//!         .wait()
+//!         .unwrap();
+//!     assert_eq!(event, Event::Number(Some(5)));
+//! }
+//! ```
+//!
+//! Each call to [`wait`](Select::wait) handles exactly one event. To handle events in a loop,
+//! build a new `Select` on each iteration.
+
+use crate::{
+    Child, Deserializer, Duplex, Object, Receiver,
+    blocking::{Blocking, block_on},
+};
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, BorrowedFd, RawFd as RawDescriptor};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, BorrowedSocket, RawSocket as RawDescriptor};
+
+/// Channels that can be waited for by [`Select`].
+///
+/// This trait is sealed and implemented for [`Receiver`] and [`Duplex`].
+pub trait Selectable: private::Sealed {
+    /// The type of the messages received from the channel.
+    type Message: Object;
+}
+
+impl<T: Object> Selectable for Receiver<T> {
+    type Message = T;
+}
+impl<S: Object, R: Object> Selectable for Duplex<S, R> {
+    type Message = R;
+}
+
+mod private {
+    use crate::{Duplex, Object, Receiver, Serializer, blocking::Blocking};
+    use std::io::Result;
+
+    pub trait Sealed {
+        fn stream(&self) -> &Blocking;
+        fn recv_serialized(&mut self) -> Result<Option<Serializer>>;
+    }
+
+    impl<T: Object> Sealed for Receiver<T> {
+        fn stream(&self) -> &Blocking {
+            &self.0.fd
+        }
+        fn recv_serialized(&mut self) -> Result<Option<Serializer>> {
+            super::block_on(self.0.recv_serialized(false))
+        }
+    }
+
+    impl<S: Object, R: Object> Sealed for Duplex<S, R> {
+        fn stream(&self) -> &Blocking {
+            &self.0.fd
+        }
+        fn recv_serialized(&mut self) -> Result<Option<Serializer>> {
+            super::block_on(self.0.recv_serialized(false))
+        }
+    }
+}
+
+// An event `Select` waits for: the stream becoming readable.
+struct Arm<'a, U> {
+    // Borrowed for `'a` by `handle`.
+    stream: RawDescriptor,
+    handle: Box<dyn FnOnce() -> Result<U> + 'a>,
+}
+
+/// A builder that waits for one of several events.
+///
+/// Register the events with [`recv`](Self::recv), [`exited`](Self::exited), and
+/// [`timeout`](Self::timeout), then call [`wait`](Self::wait). Each event has a handler that
+/// produces a value of type `U`, which `wait` returns.
+///
+/// If several events are ready at once, the one registered first wins.
+pub struct Select<'a, U> {
+    arms: Vec<Arm<'a, U>>,
+    timeout: Option<(Duration, Box<dyn FnOnce() -> U + 'a>)>,
+}
+
+impl<'a, U> Select<'a, U> {
+    /// Create a builder without any events.
+    pub fn new() -> Self {
+        Self {
+            arms: Vec::new(),
+            timeout: None,
+        }
+    }
+
+    /// Wait for a message from a [`Receiver`] or a [`Duplex`].
+    ///
+    /// When a message arrives, it's received in full and passed to `handler`. If the other side
+    /// has dropped the channel, `handler` is called with `None`.
+    pub fn recv<C: Selectable>(
+        mut self,
+        channel: &'a mut C,
+        handler: impl FnOnce(Option<C::Message>) -> U + 'a,
+    ) -> Self {
+        self.arms.push(Arm {
+            stream: as_raw(channel.stream()),
+            handle: Box::new(move || {
+                let serialized = channel.recv_serialized()?;
+                Ok(handler(serialized.map(|serialized| unsafe {
+                    Deserializer::from(serialized).deserialize()
+                })))
+            }),
+        });
+        self
+    }
+
+    /// Wait for a child to finish.
+    ///
+    /// `handler` is called when the function returns or the process exits. The child is not
+    /// joined: call [`Child::join`] afterwards to obtain the return value, which should not block
+    /// for long at that point.
+    pub fn exited<T: Object>(
+        mut self,
+        child: &'a Child<T>,
+        handler: impl FnOnce() -> U + 'a,
+    ) -> Self {
+        self.arms.push(Arm {
+            stream: as_raw(child.0.output_stream()),
+            handle: Box::new(move || Ok(handler())),
+        });
+        self
+    }
+
+    /// Give up waiting after `timeout`, calling `handler` instead.
+    ///
+    /// The timeout is counted from the call to [`wait`](Self::wait). Calling this method again
+    /// replaces the previous timeout.
+    pub fn timeout(mut self, timeout: Duration, handler: impl FnOnce() -> U + 'a) -> Self {
+        self.timeout = Some((timeout, Box::new(handler)));
+        self
+    }
+
+    /// Block until one of the events happens and return the result of its handler.
+    ///
+    /// Errors receiving the message are returned as is. If no events are registered, an error of
+    /// kind [`InvalidInput`](ErrorKind::InvalidInput) is returned instead of blocking forever.
+    pub fn wait(mut self) -> Result<U> {
+        if self.arms.is_empty() {
+            let Some((timeout, handler)) = self.timeout else {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Select has no events to wait for",
+                ));
+            };
+            std::thread::sleep(timeout);
+            return Ok(handler());
+        }
+
+        // A deadline too far in the future to represent means no timeout.
+        let deadline = self
+            .timeout
+            .as_ref()
+            .and_then(|(timeout, _)| Instant::now().checked_add(*timeout));
+        // SAFETY: the handlers borrow the channels and children for `'a`, so the streams are open.
+        #[cfg(unix)]
+        let streams: Vec<_> = self
+            .arms
+            .iter()
+            .map(|arm| unsafe { BorrowedFd::borrow_raw(arm.stream) })
+            .collect();
+        #[cfg(windows)]
+        let streams: Vec<_> = self
+            .arms
+            .iter()
+            .map(|arm| unsafe { BorrowedSocket::borrow_raw(arm.stream) })
+            .collect();
+        let ready = crate::internals::wait_any_readable(&streams, deadline)?;
+        drop(streams);
+
+        match ready {
+            Some(index) => (self.arms.swap_remove(index).handle)(),
+            None => {
+                let (_, handler) = self.timeout.take().expect("Timed out without a timeout");
+                Ok(handler())
+            }
+        }
+    }
+}
+
+impl<U> Default for Select<'_, U> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<U> fmt::Debug for Select<'_, U> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Select")
+            .field("events", &self.arms.len())
+            .field(
+                "timeout",
+                &self.timeout.as_ref().map(|(timeout, _)| timeout),
+            )
+            .finish()
+    }
+}
+
+fn as_raw(stream: &Blocking) -> RawDescriptor {
+    #[cfg(unix)]
+    return stream.as_raw_fd();
+    #[cfg(windows)]
+    return stream.as_raw_socket();
+}
@@ -5,6 +5,7 @@
 
 use crate::owning_ref::OwningRef;
 use std::fmt;
+use std::io::{Error, Result};
 #[cfg(unix)]
 use std::os::unix::io::OwnedFd;
 #[cfg(windows)]
@@ -22,6 +23,8 @@ pub struct Serializer {
     pub(crate) handles: Vec<OwnedHandle>,
     #[cfg(windows)]
     pub(crate) sockets: Vec<OwnedSocket>,
+    // The first error reported by an object that cannot be serialized, see `fail`.
+    pub(crate) error: Option<Error>,
 }
 
 impl Serializer {
@@ -35,6 +38,22 @@ impl Serializer {
             handles: Vec::new(),
             #[cfg(windows)]
             sockets: Vec::new(),
+            error: None,
+        }
+    }
+
+    // Report that an object cannot be serialized. `serialize_self` has no way to return errors, so
+    // the object is serialized as usual, and the error is returned by `check` afterwards, before
+    // the data is sent anywhere.
+    pub(crate) fn fail(&mut self, error: Error) {
+        self.error.get_or_insert(error);
+    }
+
+    // Return the error reported by `fail`, if any.
+    pub(crate) fn check(&mut self) -> Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
         }
     }
 
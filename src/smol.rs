@@ -2,12 +2,19 @@
 //!
 //! Check out the docs at [`asynchronous`] for more information.
 
-use crate::{Object, asynchronous};
+use crate::{Object, SpawnOptions, asynchronous};
+#[cfg(unix)]
+use std::io::ErrorKind;
 use std::io::Result;
 #[cfg(unix)]
 use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
 #[cfg(windows)]
 use std::os::windows::io::{AsRawSocket, AsSocket, BorrowedSocket, RawSocket};
+#[cfg(windows)]
+use std::pin::Pin;
+#[cfg(unix)]
+use std::task::ready;
+use std::task::{Context, Poll};
 
 /// `smol` marker type.
 #[derive(Debug, Object)]
@@ -43,6 +50,42 @@ unsafe impl asynchronous::AsyncStream for Smol {
         self.0.read_exact(buf).await?;
         Ok(())
     }
+
+    #[cfg(unix)]
+    fn poll_blocking_write<T>(
+        &self,
+        cx: &mut Context<'_>,
+        mut f: impl FnMut() -> Result<T>,
+    ) -> Poll<Result<T>> {
+        loop {
+            match f() {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => ready!(self.0.poll_writable(cx))?,
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+    #[cfg(windows)]
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        futures_lite::io::AsyncWrite::poll_write(Pin::new(&mut self.0), cx, buf)
+    }
+
+    #[cfg(unix)]
+    fn poll_blocking_read<T>(
+        &self,
+        cx: &mut Context<'_>,
+        mut f: impl FnMut() -> Result<T>,
+    ) -> Poll<Result<T>> {
+        loop {
+            match f() {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => ready!(self.0.poll_readable(cx))?,
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+    #[cfg(windows)]
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        futures_lite::io::AsyncRead::poll_read(Pin::new(&mut self.0), cx, buf)
+    }
 }
 
 #[cfg(unix)]
@@ -87,9 +130,18 @@ pub type Receiver<T> = asynchronous::Receiver<Smol, T>;
 /// is the type of the objects the other side sends via the channel and this side receives.
 pub type Duplex<S, R> = asynchronous::Duplex<Smol, S, R>;
 
+/// The sending half of a [`Duplex`], created by [`Duplex::split`](asynchronous::Duplex::split).
+pub type SendHalf<S> = asynchronous::SendHalf<Smol, S>;
+
+/// The receiving half of a [`Duplex`], created by [`Duplex::split`](asynchronous::Duplex::split).
+pub type RecvHalf<R> = asynchronous::RecvHalf<Smol, R>;
+
 /// The subprocess object created by calling `spawn_smol` on a function annotated with `#[func]`.
 pub type Child<T> = asynchronous::Child<Smol, T>;
 
+/// A client that sends pipelined requests over a duplex, see [`multiplex`](crate::multiplex).
+pub type Multiplexed<S, R> = crate::multiplex::Multiplexed<Smol, S, R>;
+
 /// Create a unidirectional channel.
 pub fn channel<T: Object>() -> Result<(Sender<T>, Receiver<T>)> {
     asynchronous::channel::<Smol, T>()
@@ -107,7 +159,9 @@ pub async unsafe fn spawn<
     Ret: Object,
 >(
     func: Func,
+    function: &'static str,
+    options: &SpawnOptions,
     args: Args,
 ) -> Result<Child<Ret>> {
-    unsafe { asynchronous::spawn::<Smol, _, _, _>(func, args).await }
+    unsafe { asynchronous::spawn::<Smol, _, _, _>(func, function, options, args).await }
 }
@@ -2,12 +2,17 @@
 //!
 //! Check out the docs at [`asynchronous`] for more information.
 
-use crate::{Object, asynchronous};
+use crate::{Object, SpawnOptions, asynchronous};
+#[cfg(unix)]
+use std::io::ErrorKind;
 use std::io::Result;
 #[cfg(unix)]
 use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
 #[cfg(windows)]
 use std::os::windows::io::{AsRawSocket, AsSocket, BorrowedSocket, RawSocket};
+#[cfg(windows)]
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
 
 /// `tokio` marker struct.
 #[derive(Debug, Object)]
@@ -45,6 +50,50 @@ unsafe impl asynchronous::AsyncStream for Tokio {
         self.0.read_exact(buf).await?;
         Ok(())
     }
+
+    #[cfg(unix)]
+    fn poll_blocking_write<T>(
+        &self,
+        cx: &mut Context<'_>,
+        mut f: impl FnMut() -> Result<T>,
+    ) -> Poll<Result<T>> {
+        loop {
+            ready!(self.0.poll_write_ready(cx))?;
+            match self.0.try_io(tokio::io::Interest::WRITABLE, &mut f) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+    #[cfg(windows)]
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        tokio::io::AsyncWrite::poll_write(Pin::new(&mut self.0), cx, buf)
+    }
+
+    #[cfg(unix)]
+    fn poll_blocking_read<T>(
+        &self,
+        cx: &mut Context<'_>,
+        mut f: impl FnMut() -> Result<T>,
+    ) -> Poll<Result<T>> {
+        loop {
+            ready!(self.0.poll_read_ready(cx))?;
+            match self.0.try_io(tokio::io::Interest::READABLE, &mut f) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+    #[cfg(windows)]
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        let mut buf = tokio::io::ReadBuf::new(buf);
+        ready!(tokio::io::AsyncRead::poll_read(
+            Pin::new(&mut self.0),
+            cx,
+            &mut buf
+        ))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
 }
 
 #[cfg(unix)]
@@ -89,9 +138,18 @@ pub type Receiver<T> = asynchronous::Receiver<Tokio, T>;
 /// is the type of the objects the other side sends via the channel and this side receives.
 pub type Duplex<S, R> = asynchronous::Duplex<Tokio, S, R>;
 
+/// The sending half of a [`Duplex`], created by [`Duplex::split`](asynchronous::Duplex::split).
+pub type SendHalf<S> = asynchronous::SendHalf<Tokio, S>;
+
+/// The receiving half of a [`Duplex`], created by [`Duplex::split`](asynchronous::Duplex::split).
+pub type RecvHalf<R> = asynchronous::RecvHalf<Tokio, R>;
+
 /// The subprocess object created by calling `spawn_tokio` on a function annotated with `#[func]`.
 pub type Child<T> = asynchronous::Child<Tokio, T>;
 
+/// A client that sends pipelined requests over a duplex, see [`multiplex`](crate::multiplex).
+pub type Multiplexed<S, R> = crate::multiplex::Multiplexed<Tokio, S, R>;
+
 /// Create a unidirectional channel.
 pub fn channel<T: Object>() -> Result<(Sender<T>, Receiver<T>)> {
     asynchronous::channel::<Tokio, T>()
@@ -109,7 +167,9 @@ pub async unsafe fn spawn<
     Ret: Object,
 >(
     func: Func,
+    function: &'static str,
+    options: &SpawnOptions,
     args: Args,
 ) -> Result<Child<Ret>> {
-    unsafe { asynchronous::spawn::<Tokio, _, _, _>(func, args).await }
+    unsafe { asynchronous::spawn::<Tokio, _, _, _>(func, function, options, args).await }
 }
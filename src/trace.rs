@@ -0,0 +1,101 @@
+//! Channel traffic tracing, enabled by the `CROSSMIST_TRACE` environment variable.
+
+use crate::asynchronous::Entry;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, BorrowedFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, BorrowedSocket};
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Direction {
+    Send,
+    Recv,
+}
+
+/// Whether `CROSSMIST_TRACE` is set to a non-empty value other than `0`.
+pub(crate) fn is_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        std::env::var_os("CROSSMIST_TRACE").is_some_and(|value| !value.is_empty() && value != "0")
+    })
+}
+
+/// Describe a channel endpoint in a way that can be matched across processes.
+#[cfg(unix)]
+pub(crate) fn channel_id(fd: BorrowedFd<'_>) -> String {
+    // The inode number identifies the socket in all processes it's passed to, while the fd is what
+    // shows up in strace and gdb.
+    match rustix::fs::fstat(fd) {
+        Ok(stat) => format!("fd {} (socket {})", fd.as_raw_fd(), stat.st_ino),
+        Err(_) => format!("fd {}", fd.as_raw_fd()),
+    }
+}
+
+/// Describe a channel endpoint.
+#[cfg(windows)]
+pub(crate) fn channel_id(socket: BorrowedSocket<'_>) -> String {
+    // Windows offers nothing to match sockets across processes by, so this is only useful within
+    // one process.
+    format!("socket {}", socket.as_raw_socket())
+}
+
+/// The name of a message type as shown in traces.
+pub(crate) fn type_name<T: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    // The messages that start children consist of crossmist internals, which are of no use in
+    // traces.
+    let entry = std::any::type_name::<Entry<()>>()
+        .strip_suffix("<()>")
+        .unwrap();
+    match name.strip_prefix(entry) {
+        Some(rest) if rest.starts_with('<') => "crossmist entry",
+        _ => name,
+    }
+}
+
+/// How many bytes of the payload are logged.
+const DATA_PREFIX: usize = 32;
+
+/// Log a message that was fully sent or received.
+///
+/// `fds` counts handles and sockets on Windows. Packets only exist on Unix, where messages are
+/// split into datagrams.
+pub(crate) fn message(
+    channel: &str,
+    direction: Direction,
+    type_name: &str,
+    data: &[u8],
+    fds: usize,
+    packets: Option<usize>,
+) {
+    let direction = match direction {
+        Direction::Send => "sent",
+        Direction::Recv => "received",
+    };
+    let mut hex: String = data
+        .iter()
+        .take(DATA_PREFIX)
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    if data.len() > DATA_PREFIX {
+        hex.push_str("...");
+    }
+    let packets = match packets {
+        Some(packets) => format!(" packets={packets}"),
+        None => String::new(),
+    };
+    eprintln!(
+        "crossmist[{}]: {channel} {direction} {type_name}: bytes={} fds={fds}{packets} data={hex}",
+        std::process::id(),
+        data.len(),
+    );
+}
+
+/// Log that the peer has closed the channel.
+pub(crate) fn closed(channel: &str, type_name: &str) {
+    eprintln!(
+        "crossmist[{}]: {channel} closed while waiting for {type_name}",
+        std::process::id(),
+    );
+}
@@ -0,0 +1,215 @@
+//! Integration with the [`tracing`](https://crates.io/crates/tracing) crate.
+//!
+//! With the `tracing` feature enabled, children run the function inside a `crossmist::child` span
+//! with `pid` and `function` fields, so events emitted by the child carry them. The span also
+//! records the process ID of the parent and the ID of the parent's span that was current during
+//! spawning as `parent_pid` and `parent_span`.
+//!
+//! Spans can't have parents in other processes, so the child span is nested under a
+//! `crossmist::parent` span that stands in for the parent's span. It has the fields `pid`, `span`,
+//! and `span_name`, which identify the span of the parent, so that the child's spans show up under
+//! it in any collector. In [in-process mode](crate::set_in_process), the child span is nested under
+//! the parent's span directly. To link the spans across processes in a way a specific collector
+//! understands, e.g. with OpenTelemetry, install a propagator with [`set_propagator`].
+//!
+//! To attach the context to individual messages, e.g. requests sent with
+//! [`Duplex::request`](crate::Duplex::request), wrap them in [`Traced`]:
+//!
+//! ```standalone_crate
+//! use crossmist::{Duplex, tracing::Traced};
+//!
+//! #[crossmist::func]
+//! fn server(mut chan: Duplex<i32, Traced<i32>>) {
+//!     while let Some(request) = chan.recv().unwrap() {
+//!         let (span, value) = request.into_parts();
+//!         let _guard = span.enter();
+//!         tracing::info!(value, "handling request");
+//!         chan.send(value * 2).unwrap();
+//!     }
+//! }
+//!
+//! fn main() {
+//!     crossmist::init();
+//!     let (mut ours, theirs) = crossmist::duplex().unwrap();
+//!     let child = server.spawn(theirs).unwrap();
+//!     let span = tracing::info_span!("request");
+//!     let _guard = span.enter();
+//!     assert_eq!(ours.request(Traced::new(5)).unwrap(), 10);
+//!     drop(ours);
+//!     child.join().unwrap();
+//! }
+//! ```
+
+use crate::Object;
+use std::sync::OnceLock;
+use tracing::{Span, field, span::Id};
+
+/// Functions that transfer span context between processes, see [`set_propagator`].
+struct Propagator {
+    inject: fn(&mut Vec<(String, String)>),
+    extract: fn(&Span, &[(String, String)]),
+}
+
+static PROPAGATOR: OnceLock<Propagator> = OnceLock::new();
+
+/// Install functions that transfer span context between processes.
+///
+/// `inject` is called by the sender with the span to propagate entered, and should add key-value
+/// pairs describing it to the carrier. `extract` is called by the receiver with the span created
+/// on its side and the carrier, and should link the span to the remote context. For example, with
+/// `tracing-opentelemetry`, `inject` would use a `TextMapPropagator` to inject
+/// `Span::current().context()`, and `extract` would call `span.set_parent(...)` with the extracted
+/// context.
+///
+/// The propagator must be installed both in the parent and in the children, so call this function
+/// before [`crossmist::init`](crate::init) or from an [`on_child_start`](crate::Init::on_child_start)
+/// hook.
+///
+/// # Panics
+///
+/// Panics if a propagator has already been installed.
+pub fn set_propagator(
+    inject: fn(&mut Vec<(String, String)>),
+    extract: fn(&Span, &[(String, String)]),
+) {
+    if PROPAGATOR.set(Propagator { inject, extract }).is_err() {
+        panic!("crossmist::tracing::set_propagator() is called twice");
+    }
+}
+
+/// The span context of another process.
+#[derive(Clone, Debug, Object)]
+pub struct SpanContext {
+    parent_pid: u32,
+    parent_span: Option<u64>,
+    parent_span_name: Option<String>,
+    carrier: Vec<(String, String)>,
+}
+
+impl SpanContext {
+    /// Capture the context of the current span.
+    pub fn current() -> Self {
+        let mut carrier = Vec::new();
+        if let Some(propagator) = PROPAGATOR.get() {
+            (propagator.inject)(&mut carrier);
+        }
+        let span = Span::current();
+        Self {
+            parent_pid: std::process::id(),
+            parent_span: span.id().map(|id| id.into_u64()),
+            parent_span_name: span.metadata().map(|metadata| metadata.name().to_string()),
+            carrier,
+        }
+    }
+
+    /// The process ID of the process the context was captured in.
+    pub fn parent_pid(&self) -> u32 {
+        self.parent_pid
+    }
+
+    /// The ID of the span the context was captured from in its process, if any.
+    pub fn parent_span(&self) -> Option<u64> {
+        self.parent_span
+    }
+
+    /// The name of the span the context was captured from, if any.
+    pub fn parent_span_name(&self) -> Option<&str> {
+        self.parent_span_name.as_deref()
+    }
+
+    /// The key-value pairs added by the propagator.
+    pub fn carrier(&self) -> &[(String, String)] {
+        &self.carrier
+    }
+
+    // A span standing in for the span the context was captured from, if that one is in another
+    // process, where it can't be referred to directly.
+    fn remote_parent(&self) -> Option<Span> {
+        let id = self.parent_span?;
+        if self.parent_pid == std::process::id() {
+            return None;
+        }
+        Some(tracing::info_span!(
+            parent: None,
+            "crossmist::parent",
+            pid = self.parent_pid,
+            span = id,
+            span_name = self.parent_span_name.as_deref(),
+        ))
+    }
+
+    // Link a span created on our side to the context.
+    fn link(&self, span: Span) -> Span {
+        if let Some(parent_span) = self.parent_span {
+            span.record("parent_span", parent_span);
+        }
+        if let Some(propagator) = PROPAGATOR.get() {
+            (propagator.extract)(&span, &self.carrier);
+        }
+        span
+    }
+
+    pub(crate) fn child_span(&self, function: &str) -> Span {
+        // In in-process mode, the parent's span is the current one.
+        let parent = self.remote_parent().unwrap_or_else(Span::current);
+        self.link(tracing::info_span!(
+            parent: &parent,
+            "crossmist::child",
+            pid = std::process::id(),
+            function,
+            parent_pid = self.parent_pid,
+            parent_span = field::Empty,
+        ))
+    }
+
+    fn message_span(&self) -> Span {
+        let remote_parent = self.remote_parent();
+        let span = self.link(tracing::info_span!(
+            "crossmist::message",
+            pid = std::process::id(),
+            parent_pid = self.parent_pid,
+            parent_span = field::Empty,
+        ));
+        match remote_parent {
+            Some(parent) => span.follows_from(&parent),
+            None => span.follows_from(self.parent_span.map(Id::from_u64)),
+        };
+        span
+    }
+}
+
+/// A message together with the span context of its sender.
+#[derive(Debug, Object)]
+pub struct Traced<T: Object> {
+    context: SpanContext,
+    value: T,
+}
+
+impl<T: Object> Traced<T> {
+    /// Wrap a value, capturing the context of the current span.
+    pub fn new(value: T) -> Self {
+        Self {
+            context: SpanContext::current(),
+            value,
+        }
+    }
+
+    /// The span context of the sender.
+    pub fn context(&self) -> &SpanContext {
+        &self.context
+    }
+
+    /// Create a `crossmist::message` span linked to the sender's context and return it together
+    /// with the value.
+    ///
+    /// The span is a child of the receiver's current span and follows from the sender's span, or
+    /// from a `crossmist::parent` span standing in for it if the sender is another process.
+    pub fn into_parts(self) -> (Span, T) {
+        (self.context.message_span(), self.value)
+    }
+
+    /// Discard the context and return the value.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
@@ -0,0 +1,97 @@
+use crossmist::{Object, Receiver, channel};
+
+#[crossmist::func]
+fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+#[crossmist::test]
+fn simple() {
+    assert_eq!(add.run(5, 7).unwrap(), 12);
+}
+
+#[crossmist::test]
+fn with_channel() {
+    #[crossmist::func]
+    fn inner(mut rx: Receiver<i32>) -> i32 {
+        rx.recv().unwrap().unwrap()
+    }
+
+    let (mut tx, rx) = channel::<i32>().unwrap();
+    let child = inner.spawn(rx).unwrap();
+    tx.send(123).unwrap();
+    assert_eq!(child.join().unwrap(), 123);
+}
+
+#[crossmist::test]
+fn from_thread() {
+    std::thread::spawn(|| assert_eq!(add.run(1, 2).unwrap(), 3))
+        .join()
+        .unwrap();
+}
+
+#[crossmist::test]
+fn returns_result() -> std::io::Result<()> {
+    assert_eq!(add.run(-1, 1)?, 0);
+    Ok(())
+}
+
+#[crossmist::test]
+#[should_panic(expected = "The subprocess")]
+fn child_panics() {
+    #[crossmist::func]
+    fn inner() {
+        panic!("oops");
+    }
+    inner.run().unwrap();
+}
+
+#[crossmist::test]
+fn child_catches_panic() {
+    #[crossmist::func]
+    fn inner() -> bool {
+        std::panic::catch_unwind(|| panic!("oops")).is_err()
+    }
+    assert!(inner.run().unwrap());
+}
+
+#[derive(Debug, PartialEq, Object)]
+struct Pair(String, i32);
+
+#[crossmist::test]
+fn nested() {
+    #[crossmist::func]
+    fn outer(pair: Pair) -> Pair {
+        Pair(pair.0 + "!", add.run(pair.1, 1).unwrap())
+    }
+    assert_eq!(
+        outer.run(Pair("hi".to_string(), 1)).unwrap(),
+        Pair("hi!".to_string(), 2)
+    );
+}
+
+#[cfg(feature = "tokio")]
+#[crossmist::test(tokio)]
+async fn tokio_runtime() {
+    assert_eq!(add.run_tokio(5, 7).await.unwrap(), 12);
+}
+
+#[cfg(feature = "smol")]
+#[crossmist::test(smol)]
+async fn smol_runtime() {
+    assert_eq!(add.run_smol(5, 7).await.unwrap(), 12);
+}
+
+#[cfg(unix)]
+#[crossmist::test]
+fn wrapper() {
+    #[crossmist::func]
+    fn wrapped_by() -> Option<String> {
+        std::env::var("CROSSMIST_WRAPPED_BY").ok()
+    }
+    let options = crossmist::SpawnOptions::new().wrapper(["env", "CROSSMIST_WRAPPED_BY=env"]);
+    assert_eq!(
+        wrapped_by.run_with(&options).unwrap().as_deref(),
+        Some("env")
+    );
+}
@@ -0,0 +1,119 @@
+use log::{Log, Metadata, Record};
+use std::sync::Mutex;
+
+// This target has its own `main` to install the logger in the parent.
+#[allow(dead_code, unused_imports, unused_macros)]
+mod testing;
+use testing::test;
+
+struct Capture;
+
+static RECORDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+impl Log for Capture {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        RECORDS.lock().unwrap().push(format!(
+            "{} {} {}",
+            record.level(),
+            record.target(),
+            record.args(),
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+fn records_containing(needle: &str) -> Vec<String> {
+    RECORDS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|record| record.contains(needle))
+        .cloned()
+        .collect()
+}
+
+fn main() {
+    // Children must not set their own logger, or nothing will be forwarded.
+    testing::run(crossmist::Init::new().on_parent_start(|| {
+        log::set_logger(&Capture).unwrap();
+        log::set_max_level(log::LevelFilter::Debug);
+    }));
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn forwarded() {
+    #[crossmist::func]
+    fn inner(token: String) {
+        log::info!("hello from {token}");
+        log::debug!(target: "custom", "debug from {token}");
+        log::trace!("trace from {token}");
+    }
+
+    let child = inner.spawn("forwarded".to_string()).unwrap();
+    let pid = child.id();
+    child.join().unwrap();
+    assert_eq!(
+        records_containing("from forwarded"),
+        [
+            format!("INFO logging::inner[{pid}]::logging hello from forwarded"),
+            format!("DEBUG logging::inner[{pid}]::custom debug from forwarded"),
+        ]
+    );
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn nested() {
+    #[crossmist::func]
+    fn inner() {
+        log::warn!("hello from nested");
+    }
+
+    #[crossmist::func]
+    fn outer() -> i32 {
+        let child = inner.spawn().unwrap();
+        let pid = child.id();
+        child.join().unwrap();
+        pid
+    }
+
+    let child = outer.spawn().unwrap();
+    let outer_pid = child.id();
+    let inner_pid = child.join().unwrap();
+    assert_eq!(
+        records_containing("from nested"),
+        [format!(
+            "WARN logging::outer[{outer_pid}]::logging::inner[{inner_pid}]::logging hello from \
+             nested"
+        )]
+    );
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn in_process() {
+    #[crossmist::func]
+    fn inner() {
+        log::info!("hello from in-process");
+    }
+
+    #[crossmist::func]
+    fn outer() {
+        crossmist::set_in_process(true);
+        inner.run().unwrap();
+    }
+
+    // The in-process child logs via the logger of its process directly.
+    let child = outer.spawn().unwrap();
+    let pid = child.id();
+    child.join().unwrap();
+    assert_eq!(
+        records_containing("from in-process"),
+        [format!(
+            "INFO logging::outer[{pid}]::logging hello from in-process"
+        )]
+    );
+}
@@ -311,3 +311,1125 @@ fn with_static_ref() {
         "Hello, world!"
     );
 }
+
+#[cfg(target_os = "linux")]
+#[macro_rules_attribute::apply(test!)]
+fn seccomp_compute_only() {
+    use crossmist::{SpawnOptions, options::SeccompFilter};
+
+    #[crossmist::func]
+    fn inner(mut chan: Duplex<i32, (i32, i32)>) -> i32 {
+        let mut sum = 0;
+        while let Some((x, y)) = chan.recv().unwrap() {
+            sum += x + y;
+            chan.send(x - y).unwrap();
+        }
+        sum
+    }
+
+    let options = SpawnOptions::new().seccomp(SeccompFilter::compute_only());
+    let (mut local, downstream) = duplex::<(i32, i32), i32>().unwrap();
+    let child = inner.spawn_with(&options, downstream).unwrap();
+    for (x, y) in [(5, 7), (100, 23), (3, 3)] {
+        assert_eq!(local.request((x, y)).unwrap(), x - y);
+    }
+    drop(local);
+    assert_eq!(child.join().unwrap(), 141);
+}
+
+#[cfg(target_os = "linux")]
+#[macro_rules_attribute::apply(test!)]
+fn seccomp_large_argument() {
+    use crossmist::{SpawnOptions, options::SeccompFilter};
+
+    #[crossmist::func]
+    fn inner(data: Vec<u8>) -> Vec<u8> {
+        data.into_iter().rev().collect()
+    }
+
+    // Large buffers are allocated with `mmap`, which the base filter must allow.
+    let data: Vec<u8> = (0..1 << 22).map(|i| i as u8).collect();
+    let options = SpawnOptions::new().seccomp(SeccompFilter::new());
+    let reversed = inner.run_with(&options, data.clone()).unwrap();
+    assert!(reversed.into_iter().eq(data.into_iter().rev()));
+}
+
+#[cfg(target_os = "linux")]
+#[macro_rules_attribute::apply(test!)]
+fn seccomp_violation_errno() {
+    use crossmist::{
+        SpawnOptions,
+        options::{SeccompAction, SeccompFilter},
+    };
+
+    #[crossmist::func]
+    fn inner() -> Option<i32> {
+        std::fs::File::open("/").err()?.raw_os_error()
+    }
+
+    let options = SpawnOptions::new()
+        .seccomp(SeccompFilter::compute_only().on_violation(SeccompAction::Errno(libc::EPERM)));
+    assert_eq!(inner.run_with(&options).unwrap(), Some(libc::EPERM));
+}
+
+#[cfg(target_os = "linux")]
+#[macro_rules_attribute::apply(test!)]
+fn seccomp_violation_kill() {
+    use crossmist::{SpawnOptions, options::SeccompFilter};
+
+    #[crossmist::func]
+    fn inner() -> bool {
+        std::fs::File::open("/").is_ok()
+    }
+
+    let options = SpawnOptions::new().seccomp(SeccompFilter::compute_only());
+    assert!(inner.run_with(&options).is_err());
+}
+
+#[cfg(target_os = "linux")]
+fn landlock_supported() -> bool {
+    // LANDLOCK_CREATE_RULESET_VERSION
+    unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, 0, 0, 1) > 0 }
+}
+
+#[cfg(target_os = "linux")]
+#[macro_rules_attribute::apply(test!)]
+fn landlock_denies_unlisted() {
+    use crossmist::{SpawnOptions, options::LandlockRuleset};
+    use std::path::PathBuf;
+
+    #[crossmist::func]
+    fn inner(path: PathBuf) -> Option<i32> {
+        std::fs::File::open(path).err()?.raw_os_error()
+    }
+
+    let options = SpawnOptions::new().landlock(LandlockRuleset::new().read_only("/proc"));
+    let exe = std::env::current_exe().unwrap();
+    let expected = if landlock_supported() {
+        Some(libc::EACCES)
+    } else {
+        None
+    };
+    assert_eq!(inner.run_with(&options, exe).unwrap(), expected);
+}
+
+#[cfg(target_os = "linux")]
+#[macro_rules_attribute::apply(test!)]
+fn landlock_allows_listed_and_passed() {
+    use crossmist::{SpawnOptions, options::LandlockRuleset};
+    use std::{fs::File, io::Read, path::PathBuf};
+
+    #[crossmist::func]
+    fn inner(dir: PathBuf, mut exe: File) -> (String, bool) {
+        std::fs::write(dir.join("output"), "written").unwrap();
+        let input = std::fs::read_to_string(dir.join("input")).unwrap();
+        let mut header = [0; 4];
+        exe.read_exact(&mut header).unwrap();
+        (input, &header == b"\x7fELF")
+    }
+
+    let dir = std::env::temp_dir().join(format!("crossmist-landlock-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("input"), "hello").unwrap();
+
+    let options = SpawnOptions::new().landlock(LandlockRuleset::new().read_write(&dir));
+    let exe = File::open(std::env::current_exe().unwrap()).unwrap();
+    let result = inner.run_with(&options, dir.clone(), exe);
+    let output = std::fs::read_to_string(dir.join("output"));
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(result.unwrap(), ("hello".to_string(), true));
+    assert_eq!(output.unwrap(), "written");
+}
+
+#[cfg(target_os = "linux")]
+#[macro_rules_attribute::apply(test!)]
+fn landlock_missing_path() {
+    use crossmist::{SpawnOptions, options::LandlockRuleset};
+
+    #[crossmist::func]
+    fn inner() {}
+
+    let options =
+        SpawnOptions::new().landlock(LandlockRuleset::new().read_only("/nonexistent/crossmist"));
+    if landlock_supported() {
+        assert!(inner.run_with(&options).is_err());
+    } else {
+        inner.run_with(&options).unwrap();
+    }
+}
+
+#[cfg(unix)]
+#[macro_rules_attribute::apply(test!)]
+fn drop_user_and_groups() {
+    use crossmist::SpawnOptions;
+
+    #[crossmist::func]
+    fn inner() -> (u32, u32, Vec<u32>) {
+        let mut groups = vec![0; 16];
+        let n = unsafe { libc::getgroups(groups.len() as _, groups.as_mut_ptr()) };
+        groups.truncate(n as usize);
+        unsafe { (libc::getuid(), libc::getgid(), groups) }
+    }
+
+    let options = SpawnOptions::new().uid(65534).gid(65534);
+    if unsafe { libc::geteuid() } == 0 {
+        assert_eq!(inner.run_with(&options).unwrap(), (65534, 65534, vec![]));
+        let options = options.groups([100, 65534]);
+        assert_eq!(
+            inner.run_with(&options).unwrap(),
+            (65534, 65534, vec![100, 65534])
+        );
+    } else {
+        assert!(inner.run_with(&options).is_err());
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[macro_rules_attribute::apply(test!)]
+fn clear_capabilities() {
+    use crossmist::SpawnOptions;
+
+    #[crossmist::func]
+    fn inner() -> Vec<String> {
+        std::fs::read_to_string("/proc/self/status")
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with("Cap") || line.starts_with("NoNewPrivs"))
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    #[crossmist::func]
+    fn try_setuid() -> bool {
+        let options = SpawnOptions::new().uid(65534);
+        try_setuid_inner.run_with(&options).is_err()
+    }
+
+    #[crossmist::func]
+    fn try_setuid_inner() {}
+
+    let options = SpawnOptions::new().clear_capabilities().no_new_privs();
+    for line in inner.run_with(&options).unwrap() {
+        let (name, value) = line.split_once(':').unwrap();
+        let value = value.trim();
+        match name {
+            "NoNewPrivs" => assert_eq!(value, "1"),
+            // The bounding set can't be cleared without `CAP_SETPCAP`.
+            "CapBnd" if unsafe { libc::geteuid() } != 0 => {}
+            _ => assert_eq!(value, "0000000000000000", "{name}"),
+        }
+    }
+
+    // Without capabilities, even root can't switch users, and the error is reported to the parent.
+    assert!(try_setuid.run_with(&options).unwrap());
+}
+
+#[cfg(target_os = "linux")]
+#[macro_rules_attribute::apply(test!)]
+fn scheduling_and_oom() {
+    use crossmist::{SpawnOptions, options::SchedPolicy};
+
+    fn allowed_cpus() -> Vec<usize> {
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        unsafe {
+            libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut set);
+        }
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+            .collect()
+    }
+
+    #[crossmist::func]
+    fn inner() -> (Vec<usize>, i32, i32, String) {
+        let policy = unsafe { libc::sched_getscheduler(0) };
+        let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+        let oom_score_adj = std::fs::read_to_string("/proc/self/oom_score_adj").unwrap();
+        (
+            allowed_cpus(),
+            policy,
+            nice,
+            oom_score_adj.trim().to_string(),
+        )
+    }
+
+    // CPU 0 is not necessarily available, e.g. in containers.
+    let cpu = *allowed_cpus().last().unwrap();
+    let options = SpawnOptions::new()
+        .cpu_affinity([cpu])
+        .scheduler(SchedPolicy::Batch)
+        .nice(5)
+        .oom_score_adj(500);
+    assert_eq!(
+        inner.run_with(&options).unwrap(),
+        (vec![cpu], libc::SCHED_BATCH, 5, "500".to_string())
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[macro_rules_attribute::apply(test!)]
+fn invalid_cpu_affinity() {
+    use crossmist::SpawnOptions;
+
+    #[crossmist::func]
+    fn inner() {}
+
+    let options = SpawnOptions::new().cpu_affinity([100_000]);
+    assert!(inner.run_with(&options).is_err());
+}
+
+#[cfg(unix)]
+#[macro_rules_attribute::apply(test!)]
+fn leaked_fds() {
+    use crossmist::SpawnOptions;
+    use std::os::unix::io::{AsRawFd, RawFd};
+
+    #[crossmist::func]
+    fn is_open(fd: RawFd) -> bool {
+        unsafe { libc::fcntl(fd, libc::F_GETFD) != -1 }
+    }
+
+    let file = std::fs::File::open("/dev/null").unwrap();
+    let fd = file.as_raw_fd();
+    // Emulate a library that doesn't set `O_CLOEXEC`.
+    unsafe {
+        libc::fcntl(fd, libc::F_SETFD, 0);
+    }
+
+    assert!(!is_open.run(fd).unwrap());
+    assert!(
+        is_open
+            .run_with(&SpawnOptions::new().inherit_fds(), fd)
+            .unwrap()
+    );
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn init_hooks() {
+    use std::sync::atomic::Ordering;
+
+    #[crossmist::func]
+    fn inner() -> (bool, bool) {
+        (
+            testing::CHILD_STARTED.load(Ordering::Relaxed),
+            testing::PARENT_STARTED.load(Ordering::Relaxed),
+        )
+    }
+
+    assert!(!testing::CHILD_STARTED.load(Ordering::Relaxed));
+    assert!(testing::PARENT_STARTED.load(Ordering::Relaxed));
+    assert_eq!(inner.run().unwrap(), (true, false));
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn in_process() {
+    #[crossmist::func]
+    fn pid_and_sum(mut rx: Receiver<i32>) -> (u32, i32) {
+        let mut sum = 0;
+        while let Some(value) = rx.recv().unwrap() {
+            sum += value;
+        }
+        (std::process::id(), sum)
+    }
+
+    #[crossmist::func]
+    fn panics() {
+        panic!("oops");
+    }
+
+    #[crossmist::func]
+    fn inner() {
+        // Toggle the mode in a subprocess, so that concurrently running tests are not affected.
+        crossmist::set_in_process(true);
+
+        let (mut tx, rx) = channel::<i32>().unwrap();
+        let child = pid_and_sum.spawn(rx).unwrap();
+        assert_eq!(child.id() as u32, std::process::id());
+        assert_eq!(
+            child.get_kill_handle().kill().unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+        for i in 1..=10 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        assert_eq!(child.join().unwrap(), (std::process::id(), 55));
+
+        assert!(panics.run().is_err());
+        assert_eq!(add_with_arguments_impl.run(5, 7).unwrap(), 12);
+    }
+
+    inner.run().unwrap();
+}
+
+#[cfg(unix)]
+#[crossmist::func]
+fn debug_wait_target() -> i32 {
+    42
+}
+
+// Resume the child with `SIGCONT` until it's joined. Signals arriving before the child starts
+// waiting are lost, so keep sending them.
+#[cfg(unix)]
+fn join_with_sigcont(child: crossmist::Child<i32>) -> i32 {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let pid = child.id();
+    let done = Arc::new(AtomicBool::new(false));
+    let resumer = std::thread::spawn({
+        let done = done.clone();
+        move || {
+            while !done.load(Ordering::Relaxed) {
+                unsafe {
+                    libc::kill(pid, libc::SIGCONT);
+                }
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+        }
+    });
+    let result = child.join().unwrap();
+    done.store(true, Ordering::Relaxed);
+    resumer.join().unwrap();
+    result
+}
+
+#[cfg(unix)]
+#[macro_rules_attribute::apply(test!)]
+fn wait_for_debugger() {
+    use crossmist::SpawnOptions;
+
+    #[crossmist::func]
+    fn with_env() -> i32 {
+        // Set the variable in a subprocess, so that concurrently running tests are not affected.
+        unsafe {
+            std::env::set_var("CROSSMIST_DEBUG_WAIT", "unrelated,debug_wait_target");
+        }
+        join_with_sigcont(debug_wait_target.spawn().unwrap())
+    }
+
+    let options = SpawnOptions::new().wait_for_debugger();
+    assert_eq!(
+        join_with_sigcont(debug_wait_target.spawn_with(&options).unwrap()),
+        42
+    );
+    assert_eq!(with_env.run().unwrap(), 42);
+}
+
+#[cfg(unix)]
+#[macro_rules_attribute::apply(test!)]
+fn wrapper() {
+    use crossmist::SpawnOptions;
+
+    #[crossmist::func]
+    fn wrapped_by() -> Option<String> {
+        std::env::var("CROSSMIST_WRAPPED_BY").ok()
+    }
+
+    assert_eq!(wrapped_by.run().unwrap(), None);
+    let options = SpawnOptions::new().wrapper(["env", "CROSSMIST_WRAPPED_BY=env"]);
+    assert_eq!(
+        wrapped_by.run_with(&options).unwrap().as_deref(),
+        Some("env")
+    );
+    assert!(
+        wrapped_by
+            .run_with(&SpawnOptions::new().wrapper(["crossmist-missing-wrapper"]))
+            .is_err()
+    );
+
+    // The variable marking a wrapped child must not leak into the children it spawns without a
+    // wrapper.
+    #[crossmist::func]
+    fn marker_visible() -> bool {
+        std::env::var_os("CROSSMIST_WRAPPED").is_some()
+    }
+    #[crossmist::func]
+    fn grandchild_marker_visible() -> bool {
+        marker_visible.run().unwrap()
+    }
+    assert!(!grandchild_marker_visible.run_with(&options).unwrap());
+}
+
+#[cfg(unix)]
+#[macro_rules_attribute::apply(test!)]
+fn trace() {
+    use crossmist::SpawnOptions;
+
+    #[crossmist::func]
+    fn traced() {
+        let (mut tx, mut rx) = crossmist::channel::<String>().unwrap();
+        tx.send("hello".to_string()).unwrap();
+        drop(tx);
+        assert_eq!(rx.recv().unwrap().as_deref(), Some("hello"));
+        assert_eq!(rx.recv().unwrap(), None);
+    }
+
+    // Redirect the whole stderr of the child, so that the traces don't end up in the test output.
+    let path = std::env::temp_dir().join(format!("crossmist-trace-{}", std::process::id()));
+    let script = format!("CROSSMIST_TRACE=1 exec \"$@\" 2>'{}'", path.display());
+    traced
+        .run_with(&SpawnOptions::new().wrapper(["sh", "-c", &script, "sh"]))
+        .unwrap();
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 4, "{log}");
+    assert!(lines[0].contains(" received crossmist entry: "), "{log}");
+    assert!(lines[1].contains(
+        " sent alloc::string::String: bytes=13 fds=0 packets=1 data=050000000000000068656c6c6f"
+    ));
+    assert!(lines[2].contains(
+        " received alloc::string::String: bytes=13 fds=0 packets=1 data=050000000000000068656c6c6f"
+    ));
+    assert!(lines[3].contains(" closed while waiting for alloc::string::String"));
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn record_and_replay() {
+    use crossmist::replay::{Recorder, Replay};
+    use std::fs::File;
+
+    #[crossmist::func]
+    fn squares(mut chan: Duplex<i32, i32>) {
+        while let Some(x) = chan.recv().unwrap() {
+            chan.send(x * x).unwrap();
+        }
+    }
+
+    let path = std::env::temp_dir().join(format!("crossmist-capture-{}", std::process::id()));
+
+    let (ours, theirs) = duplex::<i32, i32>().unwrap();
+    let child = squares.spawn(theirs).unwrap();
+    let mut chan = Recorder::new(ours, File::create(&path).unwrap()).unwrap();
+    assert_eq!(chan.request(2).unwrap(), 4);
+    assert_eq!(chan.request(3).unwrap(), 9);
+    drop(chan);
+    child.join().unwrap();
+
+    let mut replay = unsafe { Replay::<i32, _>::new(File::open(&path).unwrap()) }.unwrap();
+    assert_eq!(replay.recv().unwrap(), Some(4));
+    assert_eq!(replay.recv().unwrap(), Some(9));
+    assert_eq!(replay.recv().unwrap(), None);
+
+    // The replay can stand in for the original channel in a child.
+    #[crossmist::func]
+    fn sum(rx: Receiver<i32>) -> i32 {
+        rx.map(Result::unwrap).sum()
+    }
+    let rx = unsafe { Replay::<i32, _>::new(File::open(&path).unwrap()) }
+        .unwrap()
+        .into_receiver()
+        .unwrap();
+    assert_eq!(sum.run(rx).unwrap(), 13);
+
+    assert_eq!(
+        unsafe { Replay::<String, _>::new(File::open(&path).unwrap()) }
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::InvalidData
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[macro_rules_attribute::apply(test!)]
+fn replay_fds() {
+    use crossmist::replay::{Recorder, Replay};
+    use std::fs::File;
+    use std::os::unix::io::OwnedFd;
+
+    let (mut tx, rx) = channel::<(OwnedFd, i32)>().unwrap();
+    let mut rx = Recorder::new(rx, Vec::new()).unwrap();
+    tx.send((File::open("/dev/null").unwrap().into(), 1))
+        .unwrap();
+    assert_eq!(rx.recv().unwrap().unwrap().1, 1);
+    let (_, capture) = rx.into_inner();
+
+    let replay = unsafe { Replay::<(OwnedFd, i32), _>::new(&capture[..]) }.unwrap();
+    let err = replay.into_receiver().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+
+    let mut replay = unsafe { Replay::<(OwnedFd, i32), _>::new(&capture[..]) }.unwrap();
+    let err = replay.recv().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    assert!(err.to_string().contains("1 file descriptors (c)"), "{err}");
+    // The recording was interrupted before the channel was closed.
+    assert!(replay.recv().unwrap().is_none());
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn recv_timeout() {
+    use std::io::ErrorKind;
+    use std::time::{Duration, Instant};
+
+    let (mut tx, mut rx) = channel::<i32>().unwrap();
+    let start = Instant::now();
+    let err = rx.recv_timeout(Duration::from_millis(50)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    tx.send(5).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), Some(5));
+    drop(tx);
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), None);
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn send_timeout() {
+    use std::io::ErrorKind;
+    use std::time::Duration;
+
+    let (mut tx, mut rx) = channel::<Vec<u32>>().unwrap();
+    let mut sent = 0;
+    loop {
+        match tx.send_timeout(vec![sent; 1000], Duration::from_millis(20)) {
+            Ok(()) => sent += 1,
+            Err(err) => {
+                assert_eq!(err.kind(), ErrorKind::TimedOut);
+                break;
+            }
+        }
+    }
+    assert!(sent > 0);
+
+    for i in 0..sent {
+        assert_eq!(rx.recv().unwrap(), Some(vec![i; 1000]));
+    }
+    assert_eq!(
+        rx.recv_timeout(Duration::from_millis(20))
+            .unwrap_err()
+            .kind(),
+        ErrorKind::TimedOut
+    );
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn timeout_mid_message() {
+    use std::io::ErrorKind;
+    use std::time::Duration;
+
+    // The message doesn't fit in the socket buffer, so each side stalls halfway through it until
+    // the other one makes progress.
+    let (mut tx, mut rx) = channel::<Vec<u8>>().unwrap();
+    let big = vec![1; 1 << 22];
+    let err = tx
+        .send_timeout(big.clone(), Duration::from_millis(50))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    let err = rx.recv_timeout(Duration::from_millis(50)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+    // Both sides resume where they stopped.
+    let sender = std::thread::spawn(move || {
+        tx.flush().unwrap();
+        tx.send_timeout(vec![2; 10], Duration::from_secs(10))
+            .unwrap();
+    });
+    assert_eq!(rx.recv_timeout(Duration::from_secs(10)).unwrap(), Some(big));
+    assert_eq!(rx.recv().unwrap(), Some(vec![2; 10]));
+    sender.join().unwrap();
+    assert_eq!(rx.recv().unwrap(), None);
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn pass_unflushed() {
+    use std::io::ErrorKind;
+    use std::time::Duration;
+
+    #[crossmist::func]
+    fn inner(_tx: Sender<Vec<u8>>) {}
+
+    let (mut tx, mut rx) = channel::<Vec<u8>>().unwrap();
+    let big = vec![1; 1 << 22];
+    let err = tx
+        .send_timeout(big.clone(), Duration::from_millis(50))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    let err = rx.recv_timeout(Duration::from_millis(50)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+    // Neither side can be passed to another process halfway through a message.
+    let err = inner.spawn(tx).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    let (mut carrier, _) = channel::<Receiver<Vec<u8>>>().unwrap();
+    let err = carrier.send(rx).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn request_timeout() {
+    use std::io::ErrorKind;
+    use std::time::Duration;
+
+    #[crossmist::func]
+    fn slow_echo(mut chan: Duplex<i32, i32>) {
+        while let Some(x) = chan.recv().unwrap() {
+            std::thread::sleep(Duration::from_millis(200));
+            chan.send(x).unwrap();
+        }
+    }
+
+    let (mut ours, theirs) = duplex::<i32, i32>().unwrap();
+    let child = slow_echo.spawn(theirs).unwrap();
+    let err = ours
+        .request_timeout(1, Duration::from_millis(50))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    // The late response is not lost.
+    assert_eq!(ours.recv().unwrap(), Some(1));
+    assert_eq!(ours.request_timeout(2, Duration::from_secs(10)).unwrap(), 2);
+    drop(ours);
+    child.join().unwrap();
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn try_recv() {
+    use crossmist::TryRecv;
+
+    let (mut tx, mut rx) = channel::<Vec<u8>>().unwrap();
+    assert_eq!(rx.try_recv().unwrap(), TryRecv::Empty);
+    tx.send(vec![1, 2, 3]).unwrap();
+    assert_eq!(rx.try_recv().unwrap(), TryRecv::Value(vec![1, 2, 3]));
+    assert_eq!(rx.try_recv().unwrap(), TryRecv::Empty);
+    // Messages spanning several packets are received in full.
+    let big = vec![5; 1 << 20];
+    let big_copy = big.clone();
+    let sender = std::thread::spawn(move || {
+        tx.send(big_copy).unwrap();
+        tx
+    });
+    let received = loop {
+        match rx.try_recv().unwrap() {
+            TryRecv::Value(value) => break value,
+            TryRecv::Empty => std::thread::yield_now(),
+            TryRecv::Closed => panic!("channel closed"),
+        }
+    };
+    assert_eq!(received, big);
+    drop(sender.join().unwrap());
+    assert_eq!(rx.try_recv().unwrap(), TryRecv::Closed);
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn receiver_iterator() {
+    #[crossmist::func]
+    fn inner(mut tx: Sender<i32>) {
+        for x in 0..5 {
+            tx.send(x).unwrap();
+        }
+    }
+
+    let (tx, rx) = channel::<i32>().unwrap();
+    let child = inner.spawn(tx).unwrap();
+    let received: std::io::Result<Vec<i32>> = rx.collect();
+    assert_eq!(received.unwrap(), [0, 1, 2, 3, 4]);
+    child.join().unwrap();
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn try_send() {
+    use crossmist::TrySend;
+
+    let (mut ours, mut theirs) = duplex::<Vec<u32>, ()>().unwrap();
+    let mut sent = 0;
+    let rejected = loop {
+        match ours.try_send(vec![sent; 1000]).unwrap() {
+            TrySend::Sent => sent += 1,
+            TrySend::Full(value) => break value,
+        }
+    };
+    assert!(sent > 0);
+    assert_eq!(rejected, vec![sent; 1000]);
+
+    for i in 0..sent {
+        assert_eq!(theirs.recv().unwrap(), Some(vec![i; 1000]));
+    }
+    assert_eq!(ours.try_send(rejected).unwrap(), TrySend::Sent);
+    assert_eq!(theirs.recv().unwrap(), Some(vec![sent; 1000]));
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn select_recv() {
+    use crossmist::Select;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Number(Option<i32>),
+        Text(Option<String>),
+    }
+
+    let (mut tx, mut rx) = channel::<i32>().unwrap();
+    let (mut ours, mut theirs) = duplex::<(), String>().unwrap();
+    theirs.send("hello".to_string()).unwrap();
+    let event = Select::new()
+        .recv(&mut rx, Event::Number)
+        .recv(&mut ours, Event::Text)
+        .wait()
+        .unwrap();
+    assert_eq!(event, Event::Text(Some("hello".to_string())));
+
+    tx.send(5).unwrap();
+    let event = Select::new()
+        .recv(&mut rx, Event::Number)
+        .recv(&mut ours, Event::Text)
+        .wait()
+        .unwrap();
+    assert_eq!(event, Event::Number(Some(5)));
+
+    drop(theirs);
+    let event = Select::new()
+        .recv(&mut rx, Event::Number)
+        .recv(&mut ours, Event::Text)
+        .wait()
+        .unwrap();
+    assert_eq!(event, Event::Text(None));
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn select_timeout() {
+    use crossmist::Select;
+    use std::time::{Duration, Instant};
+
+    let (_tx, mut rx) = channel::<i32>().unwrap();
+    let start = Instant::now();
+    let value = Select::new()
+        .recv(&mut rx, |_| "message")
+        .timeout(Duration::from_millis(50), || "timeout")
+        .wait()
+        .unwrap();
+    assert_eq!(value, "timeout");
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    let err = Select::<()>::new().wait().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn select_exited() {
+    use crossmist::Select;
+
+    #[crossmist::func]
+    fn worker(mut tx: Sender<i32>, mut rx: Receiver<()>) -> i32 {
+        tx.send(1).unwrap();
+        rx.recv().unwrap();
+        2
+    }
+
+    let (tx, mut progress) = channel::<i32>().unwrap();
+    let (mut stop, rx) = channel::<()>().unwrap();
+    let child = worker.spawn(tx, rx).unwrap();
+
+    let event = Select::new()
+        .exited(&child, || None)
+        .recv(&mut progress, |value| value)
+        .wait()
+        .unwrap();
+    assert_eq!(event, Some(1));
+
+    stop.send(()).unwrap();
+    let event = Select::new()
+        .exited(&child, || None)
+        .timeout(std::time::Duration::from_secs(10), || Some(0))
+        .wait()
+        .unwrap();
+    assert_eq!(event, None);
+    assert_eq!(child.join().unwrap(), 2);
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn mpsc_fan_in() {
+    use crossmist::mpsc;
+
+    #[crossmist::func]
+    fn producer(id: u32, mut tx: mpsc::Sender<Vec<u32>>) {
+        for _ in 0..20 {
+            // Much larger than a single packet.
+            tx.send(vec![id; 100000]).unwrap();
+        }
+    }
+
+    let (tx, mut rx) = mpsc::channel::<Vec<u32>>().unwrap();
+    let children: Vec<_> = (0..4)
+        .map(|id| producer.spawn(id, tx.clone()).unwrap())
+        .collect();
+    drop(tx);
+
+    let mut counts = [0; 4];
+    while let Some(message) = rx.recv().unwrap() {
+        assert_eq!(message.len(), 100000);
+        let id = message[0];
+        assert!(message.iter().all(|&x| x == id), "messages interleaved");
+        counts[id as usize] += 1;
+    }
+    assert_eq!(counts, [20; 4]);
+    for child in children {
+        child.join().unwrap();
+    }
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn mpsc_killed_sender() {
+    use crossmist::mpsc;
+    use std::time::Duration;
+
+    #[crossmist::func]
+    fn stuck(mut tx: mpsc::Sender<Vec<u8>>) {
+        tx.send(vec![0]).unwrap();
+        // Nobody reads the channel anymore, so this blocks while holding the lock.
+        tx.send(vec![0; 1 << 22]).unwrap();
+    }
+
+    let (mut tx, mut rx) = mpsc::channel::<Vec<u8>>().unwrap();
+    let child = stuck.spawn(tx.clone()).unwrap();
+    assert_eq!(rx.recv().unwrap(), Some(vec![0]));
+    // Messages are sent packet by packet, so once the first packet of the large message arrives,
+    // the child is known to hold the lock.
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        let mut pollfd = libc::pollfd {
+            fd: rx.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        assert_eq!(unsafe { libc::poll(&mut pollfd, 1, -1) }, 1);
+    }
+    child.get_kill_handle().kill().unwrap();
+    assert!(child.join().is_err());
+
+    let err = tx
+        .send_timeout(vec![1], Duration::from_millis(50))
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn broadcast_to_children() {
+    use crossmist::broadcast::Subscriber;
+
+    #[crossmist::func]
+    fn worker(mut updates: Subscriber<Vec<u8>>) -> Vec<Vec<u8>> {
+        let mut received = Vec::new();
+        while let Some(update) = updates.recv().unwrap() {
+            received.push(update);
+        }
+        received
+    }
+
+    let mut publisher = crossmist::broadcast::<Vec<u8>>();
+    let children: Vec<_> = (0..3)
+        .map(|_| worker.spawn(publisher.subscribe().unwrap()).unwrap())
+        .collect();
+    assert_eq!(publisher.subscribers().count(), 3);
+    for i in 0..5 {
+        assert_eq!(publisher.publish(vec![i; 10]), Default::default());
+    }
+    drop(publisher);
+    for child in children {
+        assert_eq!(
+            child.join().unwrap(),
+            (0..5).map(|i| vec![i; 10]).collect::<Vec<_>>()
+        );
+    }
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn broadcast_slow_subscriber() {
+    use crossmist::TryRecv;
+
+    let mut publisher = crossmist::broadcast::<Vec<u8>>();
+    let mut fast = publisher.subscribe().unwrap();
+    let slow = publisher.subscribe().unwrap();
+    let gone = publisher.subscribe().unwrap();
+    let gone_id = gone.id();
+    drop(gone);
+
+    let published = publisher.publish(vec![0; 1000]);
+    assert_eq!(published.lagging, []);
+    assert_eq!(published.disconnected, [gone_id]);
+    assert_eq!(fast.recv().unwrap(), Some(vec![0; 1000]));
+
+    // The publisher never blocks on the slow subscriber, which eventually lags behind.
+    let mut sent = 1;
+    loop {
+        let published = publisher.publish(vec![0; 1000]);
+        assert_eq!(fast.recv().unwrap(), Some(vec![0; 1000]));
+        if !published.lagging.is_empty() {
+            assert_eq!(published.lagging, [slow.id()]);
+            break;
+        }
+        sent += 1;
+    }
+
+    assert!(publisher.unsubscribe(slow.id()));
+    assert!(!publisher.unsubscribe(slow.id()));
+    assert_eq!(publisher.subscribers().collect::<Vec<_>>(), [fast.id()]);
+
+    // The slow subscriber still gets the messages that fit in its buffer.
+    let mut slow = slow.into_receiver();
+    for _ in 0..sent {
+        assert_eq!(slow.recv().unwrap(), Some(vec![0; 1000]));
+    }
+    assert_eq!(slow.recv().unwrap(), None);
+    assert_eq!(fast.try_recv().unwrap(), TryRecv::Empty);
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn broadcast_large_message() {
+    let mut publisher = crossmist::broadcast::<Vec<u8>>();
+    let mut subscriber = publisher.subscribe().unwrap();
+    let id = subscriber.id();
+
+    // The message is much larger than the socket buffer, but publishing doesn't wait for the
+    // subscriber to read it.
+    let large = vec![1; 16 << 20];
+    assert_eq!(publisher.publish(large.clone()), Default::default());
+    // The subscriber is still receiving the large message, so it misses this one.
+    let published = publisher.publish(vec![2]);
+    assert_eq!(published.lagging, [id]);
+
+    let reader = std::thread::spawn(move || {
+        let mut received = Vec::new();
+        while let Some(value) = subscriber.recv().unwrap() {
+            received.push(value);
+        }
+        received
+    });
+    assert_eq!(publisher.flush(), []);
+    assert_eq!(publisher.publish(vec![3]), Default::default());
+    drop(publisher);
+    assert_eq!(reader.join().unwrap(), [large, vec![3]]);
+}
+
+#[derive(Debug, PartialEq, Object)]
+enum Shape {
+    Empty,
+    Circle(f64),
+    Rect { w: i32, h: i32 },
+    Named(String, Vec<Shape>),
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn enum_object() {
+    #[crossmist::func]
+    fn inner(shapes: Vec<Shape>) -> Vec<Shape> {
+        shapes.into_iter().rev().collect()
+    }
+
+    let shapes = vec![
+        Shape::Empty,
+        Shape::Circle(1.5),
+        Shape::Rect { w: 2, h: 3 },
+        Shape::Named("group".to_string(), vec![Shape::Empty, Shape::Circle(2.0)]),
+    ];
+    let mut reversed = inner.run(shapes).unwrap();
+    reversed.reverse();
+    assert_eq!(
+        reversed,
+        [
+            Shape::Empty,
+            Shape::Circle(1.5),
+            Shape::Rect { w: 2, h: 3 },
+            Shape::Named("group".to_string(), vec![Shape::Empty, Shape::Circle(2.0)]),
+        ]
+    );
+}
+
+#[crossmist::service]
+trait KeyValue {
+    fn set(&mut self, key: String, value: i32) -> Option<i32>;
+    fn get(&self, key: String) -> Option<i32>;
+    fn len(&self) -> usize;
+    fn clear(&mut self);
+}
+
+#[derive(Default)]
+struct LocalKeyValue(std::collections::HashMap<String, i32>);
+
+impl KeyValue for LocalKeyValue {
+    fn set(&mut self, key: String, value: i32) -> Option<i32> {
+        self.0.insert(key, value)
+    }
+    fn get(&self, key: String) -> Option<i32> {
+        self.0.get(&key).copied()
+    }
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn service() {
+    #[crossmist::func]
+    fn serve_kv(server: KeyValueServer) -> usize {
+        let mut kv = LocalKeyValue::default();
+        server.serve(&mut kv).unwrap();
+        kv.len()
+    }
+
+    let (mut kv, server_side) = KeyValueClient::channel().unwrap();
+    let child = serve_kv.spawn(server_side).unwrap();
+    assert_eq!(kv.set("a".to_string(), 1).unwrap(), None);
+    assert_eq!(kv.set("a".to_string(), 2).unwrap(), Some(1));
+    assert_eq!(kv.set("b".to_string(), 3).unwrap(), None);
+    assert_eq!(kv.get("a".to_string()).unwrap(), Some(2));
+    assert_eq!(kv.get("c".to_string()).unwrap(), None);
+    assert_eq!(kv.len().unwrap(), 2);
+    kv.clear().unwrap();
+    assert_eq!(kv.len().unwrap(), 0);
+    kv.set("c".to_string(), 4).unwrap();
+    drop(kv);
+    assert_eq!(child.join().unwrap(), 1);
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn service_dispatch() {
+    let mut kv = LocalKeyValue::default();
+    let response = KeyValueCall::Set {
+        key: "a".to_string(),
+        value: 1,
+    }
+    .dispatch(&mut kv);
+    assert!(matches!(response, KeyValueResponse::Set(None)));
+    let response = KeyValueCall::Len {}.dispatch(&mut kv);
+    assert!(matches!(response, KeyValueResponse::Len(1)));
+}
+
+// Argument names that are also used by the generated code, and a method named like the server's.
+#[crossmist::service]
+trait Echo {
+    fn put(&mut self, s: String, service: i32) -> String;
+    fn serve(&self, r: String) -> String;
+}
+
+struct LocalEcho(Vec<String>);
+
+impl Echo for LocalEcho {
+    fn put(&mut self, s: String, service: i32) -> String {
+        self.0.push(format!("{s}{service}"));
+        self.0.join(",")
+    }
+    fn serve(&self, r: String) -> String {
+        r.repeat(2)
+    }
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn service_argument_names() {
+    #[crossmist::func]
+    fn serve_echo(server: EchoServer) {
+        server.serve(&mut LocalEcho(Vec::new())).unwrap();
+    }
+
+    let (mut echo, server_side) = EchoClient::channel().unwrap();
+    let child = serve_echo.spawn(server_side).unwrap();
+    assert_eq!(echo.put("a".to_string(), 1).unwrap(), "a1");
+    assert_eq!(echo.put("b".to_string(), 2).unwrap(), "a1,b2");
+    assert_eq!(echo.serve("c".to_string()).unwrap(), "cc");
+    drop(echo);
+    child.join().unwrap();
+}
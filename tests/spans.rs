@@ -0,0 +1,110 @@
+// Shared between the tracing test targets, which have their own `main` to install the
+// subscriber before crossmist starts.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{
+    Event, Id, Metadata, Subscriber,
+    field::{Field, Visit},
+    span::{Attributes, Record},
+};
+use tracing_core::span::Current;
+
+// A subscriber that remembers all spans created in this process.
+pub struct Spans;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static SPANS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static METADATA: Mutex<Option<HashMap<u64, &'static Metadata<'static>>>> = Mutex::new(None);
+static FOLLOWS: Mutex<Vec<(u64, u64)>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static STACK: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Fields<'a>(&'a mut String);
+
+impl Visit for Fields<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        *self.0 += &format!(" {}={value:?}", field.name());
+    }
+}
+
+impl Subscriber for Spans {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let parent = match span.parent() {
+            Some(parent) => Some(parent.into_u64()),
+            None if span.is_contextual() => STACK.with_borrow(|stack| stack.last().copied()),
+            None => None,
+        };
+        let mut description = format!("{id} {} parent={parent:?}", span.metadata().name());
+        span.record(&mut Fields(&mut description));
+        SPANS.lock().unwrap().push(description);
+        METADATA
+            .lock()
+            .unwrap()
+            .get_or_insert_default()
+            .insert(id, span.metadata());
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut spans = SPANS.lock().unwrap();
+        let prefix = format!("{} ", span.into_u64());
+        let description = spans
+            .iter_mut()
+            .find(|description| description.starts_with(&prefix))
+            .unwrap();
+        values.record(&mut Fields(description));
+    }
+
+    fn record_follows_from(&self, span: &Id, follows: &Id) {
+        FOLLOWS
+            .lock()
+            .unwrap()
+            .push((span.into_u64(), follows.into_u64()));
+    }
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, span: &Id) {
+        STACK.with_borrow_mut(|stack| stack.push(span.into_u64()));
+    }
+
+    fn exit(&self, _span: &Id) {
+        STACK.with_borrow_mut(|stack| stack.pop());
+    }
+
+    fn current_span(&self) -> Current {
+        match STACK.with_borrow(|stack| stack.last().copied()) {
+            Some(id) => {
+                let metadata = METADATA.lock().unwrap().as_ref().unwrap()[&id];
+                Current::new(Id::from_u64(id), metadata)
+            }
+            None => Current::none(),
+        }
+    }
+}
+
+pub fn spans_named(name: &str) -> Vec<String> {
+    SPANS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|description| description.split(' ').nth(1) == Some(name))
+        .cloned()
+        .collect()
+}
+
+// The pairs of span IDs passed to `follows_from`.
+pub fn follows() -> Vec<(u64, u64)> {
+    FOLLOWS.lock().unwrap().clone()
+}
@@ -1,5 +1,6 @@
 // https://www.unwoundstack.com/blog/integration-testing-rust-binaries.html
 use libtest_mimic::{Arguments, Trial};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub struct Test {
     pub name: &'static str,
@@ -52,8 +53,18 @@ macro_rules! tokio_test {
 }
 pub(crate) use tokio_test;
 
+// Set by `crossmist::Init` hooks, so that tests can check which ones have run.
+pub static CHILD_STARTED: AtomicBool = AtomicBool::new(false);
+pub static PARENT_STARTED: AtomicBool = AtomicBool::new(false);
+
 pub fn main() {
-    crossmist::init();
+    run(crossmist::Init::new());
+}
+
+pub fn run(init: crossmist::Init) {
+    init.on_child_start(|| CHILD_STARTED.store(true, Ordering::Relaxed))
+        .on_parent_start(|| PARENT_STARTED.store(true, Ordering::Relaxed))
+        .init();
     let args = Arguments::from_args();
     let tests = inventory::iter::<Test>()
         .map(|test| Trial::test(test.name, || Ok((test.test_fn)())))
@@ -150,3 +150,229 @@ async fn exitting() {
     }
     inner.run_tokio().await.unwrap();
 }
+
+#[crossmist::service]
+trait Greeter {
+    async fn greet(&mut self, name: String) -> String;
+    fn count(&self) -> usize;
+}
+
+struct LocalGreeter(usize);
+
+impl Greeter for LocalGreeter {
+    async fn greet(&mut self, name: String) -> String {
+        self.0 += 1;
+        format!("Hello, {name}!")
+    }
+    fn count(&self) -> usize {
+        self.0
+    }
+}
+
+#[macro_rules_attribute::apply(tokio_test!)]
+async fn service() {
+    #[crossmist::func(tokio(flavor = "current_thread"))]
+    async fn inner(server: GreeterServer<Duplex<GreeterResponse, GreeterCall>>) -> usize {
+        let mut greeter = LocalGreeter(0);
+        server.serve(&mut greeter).await.unwrap();
+        greeter.0
+    }
+
+    let (ours, theirs) = duplex().unwrap();
+    let mut greeter = GreeterClient::new(ours);
+    let child = inner.spawn_tokio(GreeterServer::new(theirs)).await.unwrap();
+    assert_eq!(
+        greeter.greet("Alice".to_string()).await.unwrap(),
+        "Hello, Alice!"
+    );
+    assert_eq!(
+        greeter.greet("Bob".to_string()).await.unwrap(),
+        "Hello, Bob!"
+    );
+    assert_eq!(greeter.count().await.unwrap(), 2);
+    drop(greeter);
+    assert_eq!(child.join().await.unwrap(), 2);
+}
+
+#[macro_rules_attribute::apply(tokio_test!)]
+async fn multiplexed_requests() {
+    #[crossmist::func(tokio(flavor = "current_thread"))]
+    async fn inner(mut chan: Duplex<(u64, String), (u64, i32)>) {
+        // Answer in batches of four in reverse order to check that responses are routed by ID.
+        let mut batch = Vec::new();
+        while let Some(request) = chan.recv().await.unwrap() {
+            batch.push(request);
+            if batch.len() == 4 {
+                for (id, x) in batch.drain(..).rev() {
+                    chan.send((id, x.to_string())).await.unwrap();
+                }
+            }
+        }
+    }
+
+    let (ours, theirs) = duplex().unwrap();
+    let child = inner.spawn_tokio(theirs).await.unwrap();
+    let client = crossmist::tokio::Multiplexed::new(ours).unwrap();
+    let tasks: Vec<_> = (0..16)
+        .map(|x| {
+            let client = client.clone();
+            tokio::spawn(async move { client.request(x).await.unwrap() })
+        })
+        .collect();
+    for (x, task) in tasks.into_iter().enumerate() {
+        assert_eq!(task.await.unwrap(), x.to_string());
+    }
+    drop(client);
+    child.join().await.unwrap();
+}
+
+#[macro_rules_attribute::apply(tokio_test!)]
+async fn multiplexed_closed() {
+    #[crossmist::func(tokio(flavor = "current_thread"))]
+    async fn inner(mut chan: Duplex<(u64, ()), (u64, ())>) {
+        chan.recv().await.unwrap();
+    }
+
+    let (ours, theirs) = duplex().unwrap();
+    let child = inner.spawn_tokio(theirs).await.unwrap();
+    let client = crossmist::tokio::Multiplexed::new(ours).unwrap();
+    let err = client.request(()).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    let err = client.request(()).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    child.join().await.unwrap();
+}
+
+#[macro_rules_attribute::apply(tokio_test!)]
+async fn multiplexed_cancelled() {
+    use std::pin::pin;
+
+    let (ours, mut theirs) = duplex::<(u64, Vec<u8>), (u64, usize)>().unwrap();
+    let client = crossmist::tokio::Multiplexed::new(ours).unwrap();
+    let mut first = pin!(client.request(vec![0; 10]));
+    assert!(futures::poll!(first.as_mut()).is_pending());
+    {
+        // Nobody reads the channel yet, so the request is only partially sent when it's dropped.
+        let mut big = pin!(client.request(vec![1; 1 << 22]));
+        for _ in 0..10 {
+            assert!(futures::poll!(big.as_mut()).is_pending());
+            tokio::task::yield_now().await;
+        }
+    }
+    let third = client.request(vec![2; 5]);
+
+    let serve = async {
+        let mut requests = Vec::new();
+        for _ in 0..3 {
+            requests.push(theirs.recv().await.unwrap().unwrap());
+        }
+        for (id, data) in requests.into_iter().rev() {
+            theirs.send((id, data.len())).await.unwrap();
+        }
+    };
+    let ((), first, third) = futures::join!(serve, first, third);
+    assert_eq!(first.unwrap(), 10);
+    assert_eq!(third.unwrap(), 5);
+}
+
+#[macro_rules_attribute::apply(tokio_test!)]
+async fn split_duplex() {
+    #[crossmist::func(tokio(flavor = "current_thread"))]
+    async fn inner(mut chan: Duplex<i32, i32>) {
+        while let Some(x) = chan.recv().await.unwrap() {
+            chan.send(x + 1).await.unwrap();
+        }
+    }
+
+    let (ours, theirs) = duplex::<i32, i32>().unwrap();
+    let child = inner.spawn_tokio(theirs).await.unwrap();
+    let (mut tx, mut rx) = ours.split().unwrap();
+    let reader = tokio::spawn(async move {
+        let mut received = Vec::new();
+        for _ in 0..10 {
+            received.push(rx.recv().await.unwrap().unwrap());
+        }
+        (rx, received)
+    });
+    for x in 0..10 {
+        tx.send(x).await.unwrap();
+    }
+    let (rx, received) = reader.await.unwrap();
+    assert_eq!(received, (1..=10).collect::<Vec<_>>());
+
+    let mut chan = tx.reunite(rx).unwrap();
+    assert_eq!(chan.request(20).await.unwrap(), 21);
+    drop(chan);
+    child.join().await.unwrap();
+
+    let (a, b) = duplex::<i32, i32>().unwrap();
+    let (a_tx, _a_rx) = a.split().unwrap();
+    let (_b_tx, b_rx) = b.split().unwrap();
+    assert!(b_rx.reunite(a_tx).is_err());
+}
+
+#[macro_rules_attribute::apply(tokio_test!)]
+async fn cancel_safety() {
+    use std::pin::pin;
+
+    let (mut tx, mut rx) = channel::<Vec<u8>>().unwrap();
+    // The message doesn't fit in the socket buffer, so it's only partially sent and received before
+    // the futures are dropped.
+    let big = vec![1; 1 << 22];
+    assert!(futures::poll!(pin!(tx.send(big.clone()))).is_pending());
+    for _ in 0..10 {
+        assert!(futures::poll!(pin!(tx.flush())).is_pending());
+        assert!(futures::poll!(pin!(rx.recv())).is_pending());
+        tokio::task::yield_now().await;
+    }
+
+    let (sent, received) = futures::join!(tx.send(vec![2; 10]), async {
+        (rx.recv().await.unwrap(), rx.recv().await.unwrap())
+    });
+    sent.unwrap();
+    assert_eq!(received, (Some(big), Some(vec![2; 10])));
+}
+
+#[macro_rules_attribute::apply(tokio_test!)]
+async fn pass_after_cancelled_send() {
+    use std::pin::pin;
+
+    #[crossmist::func(tokio(flavor = "current_thread"))]
+    async fn inner(mut tx: Sender<Vec<u8>>) {
+        tx.send(vec![3; 10]).await.unwrap();
+    }
+
+    let (mut tx, mut rx) = channel::<Vec<u8>>().unwrap();
+    let big = vec![1; 1 << 22];
+    assert!(futures::poll!(pin!(tx.send(big.clone()))).is_pending());
+    tokio::task::yield_now().await;
+
+    // The rest of the message has to be flushed before the sender can be passed to a child.
+    let (flushed, received) = futures::join!(tx.flush(), rx.recv());
+    flushed.unwrap();
+    assert_eq!(received.unwrap(), Some(big));
+    let child = inner.spawn_tokio(tx).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), Some(vec![3; 10]));
+    child.join().await.unwrap();
+}
+
+#[cfg(feature = "futures")]
+#[macro_rules_attribute::apply(tokio_test!)]
+async fn stream_and_sink() {
+    use futures::{SinkExt, StreamExt, TryStreamExt};
+
+    #[crossmist::func(tokio(flavor = "current_thread"))]
+    async fn inner(rx: Receiver<i32>, tx: Sender<i32>) {
+        rx.map_ok(|x| x * 2).forward(tx).await.unwrap();
+    }
+
+    let (mut tx, rx) = channel::<i32>().unwrap();
+    let (doubled_tx, doubled_rx) = channel::<i32>().unwrap();
+    let child = inner.spawn_tokio(rx, doubled_tx).await.unwrap();
+    let mut values = futures::stream::iter([1, 2, 3]).map(Ok);
+    tx.send_all(&mut values).await.unwrap();
+    drop(tx);
+    let doubled: Vec<i32> = doubled_rx.try_collect().await.unwrap();
+    assert_eq!(doubled, [2, 4, 6]);
+    child.join().await.unwrap();
+}
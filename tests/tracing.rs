@@ -0,0 +1,111 @@
+use crossmist::{Duplex, tracing::Traced};
+use spans::{Spans, spans_named};
+use std::sync::Mutex;
+
+// This target has its own `main` to install the subscriber before crossmist starts.
+#[allow(unused_imports, unused_macros)]
+mod testing;
+use testing::test;
+
+#[allow(dead_code)]
+mod spans;
+
+static EXTRACTED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+fn main() {
+    tracing::subscriber::set_global_default(Spans).unwrap();
+    crossmist::tracing::set_propagator(
+        |carrier| carrier.push(("traceparent".to_string(), format!("{}", std::process::id()))),
+        |_span, carrier| EXTRACTED.lock().unwrap().extend_from_slice(carrier),
+    );
+    testing::main();
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn child_span() {
+    #[crossmist::func]
+    fn inner() -> (u32, Vec<String>, Vec<(String, String)>) {
+        (
+            std::process::id(),
+            spans_named("crossmist::child"),
+            EXTRACTED.lock().unwrap().clone(),
+        )
+    }
+
+    let span = tracing::info_span!("outer");
+    let parent_span = span.id().unwrap().into_u64();
+    let _guard = span.enter();
+
+    let (pid, spans, extracted) = inner.run().unwrap();
+    assert_eq!(
+        spans,
+        [format!(
+            "2 crossmist::child parent=Some(1) pid={pid} function=\"tracing::inner\" parent_pid={} \
+             parent_span={parent_span}",
+            std::process::id(),
+        )]
+    );
+    assert_eq!(
+        extracted,
+        [("traceparent".to_string(), std::process::id().to_string())]
+    );
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn in_process_child_span() {
+    #[crossmist::func]
+    fn inner() {}
+
+    #[crossmist::func]
+    fn outer() -> (u64, Vec<String>) {
+        crossmist::set_in_process(true);
+        let span = tracing::info_span!("outer");
+        let _guard = span.enter();
+        inner.run().unwrap();
+        (
+            span.id().unwrap().into_u64(),
+            spans_named("crossmist::child"),
+        )
+    }
+
+    let (parent_span, spans) = outer.run().unwrap();
+    assert_eq!(spans.len(), 2);
+    assert!(
+        spans[1].contains(&format!(" parent=Some({parent_span}) ")),
+        "{spans:?}"
+    );
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn traced_request() {
+    #[crossmist::func]
+    fn server(mut chan: Duplex<(u32, Vec<String>), Traced<i32>>) {
+        while let Some(request) = chan.recv().unwrap() {
+            let (span, value) = request.into_parts();
+            assert_eq!(value, 5);
+            drop(span);
+            chan.send((std::process::id(), spans_named("crossmist::message")))
+                .unwrap();
+        }
+    }
+
+    let (mut ours, theirs) = crossmist::duplex().unwrap();
+    let child = server.spawn(theirs).unwrap();
+
+    let span = tracing::info_span!("request");
+    let parent_span = span.id().unwrap().into_u64();
+    let _guard = span.enter();
+    let (pid, spans) = ours.request(Traced::new(5)).unwrap();
+    assert_eq!(spans.len(), 1);
+    assert!(
+        spans[0].contains(&format!(
+            " crossmist::message parent=Some(1) pid={pid} parent_pid={} \
+             parent_span={parent_span}",
+            std::process::id(),
+        )),
+        "{spans:?}"
+    );
+
+    drop(ours);
+    child.join().unwrap();
+}
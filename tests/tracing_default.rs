@@ -0,0 +1,92 @@
+use crossmist::{Duplex, tracing::Traced};
+use spans::{Spans, follows, spans_named};
+
+// Unlike the `tracing` target, this one doesn't install a propagator, so spans are linked across
+// processes by crossmist alone.
+#[allow(unused_imports, unused_macros)]
+mod testing;
+use testing::test;
+
+#[allow(dead_code)]
+mod spans;
+
+fn main() {
+    tracing::subscriber::set_global_default(Spans).unwrap();
+    testing::main();
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn child_span_nested() {
+    #[crossmist::func]
+    fn inner() -> (u32, Vec<String>, Vec<String>) {
+        (
+            std::process::id(),
+            spans_named("crossmist::parent"),
+            spans_named("crossmist::child"),
+        )
+    }
+
+    let span = tracing::info_span!("outer");
+    let parent_span = span.id().unwrap().into_u64();
+    let _guard = span.enter();
+
+    let (pid, parents, children) = inner.run().unwrap();
+    assert_eq!(
+        parents,
+        [format!(
+            "1 crossmist::parent parent=None pid={} span={parent_span} span_name=\"outer\"",
+            std::process::id(),
+        )]
+    );
+    assert_eq!(
+        children,
+        [format!(
+            "2 crossmist::child parent=Some(1) pid={pid} \
+             function=\"tracing_default::inner\" parent_pid={} parent_span={parent_span}",
+            std::process::id(),
+        )]
+    );
+}
+
+#[macro_rules_attribute::apply(test!)]
+fn traced_request_follows() {
+    #[crossmist::func]
+    fn server(mut chan: Duplex<(Vec<String>, Vec<String>, Vec<(u64, u64)>), Traced<i32>>) {
+        while let Some(request) = chan.recv().unwrap() {
+            let (span, value) = request.into_parts();
+            assert_eq!(value, 5);
+            drop(span);
+            chan.send((
+                spans_named("crossmist::parent"),
+                spans_named("crossmist::message"),
+                follows(),
+            ))
+            .unwrap();
+        }
+    }
+
+    let (mut ours, theirs) = crossmist::duplex().unwrap();
+    let child = server.spawn(theirs).unwrap();
+
+    let span = tracing::info_span!("request");
+    let parent_span = span.id().unwrap().into_u64();
+    let _guard = span.enter();
+    let (parents, messages, follows) = ours.request(Traced::new(5)).unwrap();
+
+    // The server was spawned outside of any span, so the only stand-in is for the request's span.
+    assert_eq!(parents.len(), 1);
+    assert!(
+        parents[0].ends_with(&format!(
+            " crossmist::parent parent=None pid={} span={parent_span} span_name=\"request\"",
+            std::process::id(),
+        )),
+        "{parents:?}"
+    );
+    let parent_id: u64 = parents[0].split(' ').next().unwrap().parse().unwrap();
+    assert_eq!(messages.len(), 1);
+    let message_id: u64 = messages[0].split(' ').next().unwrap().parse().unwrap();
+    assert_eq!(follows, [(message_id, parent_id)]);
+
+    drop(ours);
+    child.join().unwrap();
+}